@@ -1,39 +1,185 @@
+use crate::config::Pma;
+use crate::mem::SparseMem;
+use crate::trace::BusTrace;
+use crate::watch::{Watches, WATCH_READ, WATCH_WRITE};
 use paste::paste;
+use std::cell::{Cell, Ref, RefCell};
+#[cfg(feature = "ext_burst")]
+use std::os::raw::c_void;
 use std::rc::Rc;
+use std::sync::Arc;
 use terminus::devices::bus::{Bus, TerminusBus};
 use terminus::memory::{prelude::*, region::*, MemInfo};
 use terminus::space::Space;
 
+pub struct LocalRegion {
+    pub name: String,
+    pub id: u32,
+    pub info: MemInfo,
+    pub mem: Option<Arc<SparseMem>>,
+    pub pma: Pma,
+}
+
+pub struct SysRegion {
+    pub name: String,
+    pub info: MemInfo,
+    pub pma: Pma,
+}
+
+//regions of the system bus, shared by all buses of a cluster
+#[derive(Default)]
+pub struct SysMap(RefCell<Vec<SysRegion>>);
+
+impl SysMap {
+    pub fn add(&self, r: SysRegion) {
+        self.0.borrow_mut().push(r)
+    }
+
+    pub fn regions(&self) -> Ref<'_, Vec<SysRegion>> {
+        self.0.borrow()
+    }
+
+    //a new region must be non-empty, uniquely named and not overlap existing ones
+    pub fn check(&self, name: &str, info: &MemInfo) -> Result<(), String> {
+        if info.size == 0 {
+            return Err(format!("region {} is empty!", name));
+        }
+        let end = info
+            .base
+            .checked_add(info.size)
+            .ok_or(format!("region {} exceeds address space!", name))?;
+        for r in self.0.borrow().iter() {
+            let (n, i) = (&r.name, &r.info);
+            if n == name {
+                return Err(format!("region {} already exists!", name));
+            }
+            if info.base < i.base + i.size && i.base < end {
+                return Err(format!(
+                    "region {} [{:#x}, {:#x}) overlaps with {} [{:#x}, {:#x})!",
+                    name,
+                    info.base,
+                    end,
+                    n,
+                    i.base,
+                    i.base + i.size
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn find<T, F: Fn(&SysRegion) -> T>(&self, addr: &u64, f: F) -> Option<T> {
+        self.0
+            .borrow()
+            .iter()
+            .find(|r| *addr >= r.info.base && *addr - r.info.base < r.info.size)
+            .map(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemAccess {
+    pub addr: u64,
+    pub size: usize,
+    pub data: u64,
+    pub write: bool,
+}
+
+//axi-like response of cluster_ext_* dpi calls
+pub const RESP_OKAY: u32 = 0;
+pub const RESP_EXOKAY: u32 = 1;
+pub const RESP_SLVERR: u32 = 2;
+pub const RESP_DECERR: u32 = 3;
+
+//error response of the last ext access, shared by all buses of a cluster
+#[derive(Default)]
+pub struct BusFault(Cell<u32>);
+
+impl BusFault {
+    pub fn set(&self, resp: u32) {
+        if resp != RESP_OKAY && resp != RESP_EXOKAY {
+            self.0.set(resp)
+        }
+    }
+
+    pub fn take(&self) -> u32 {
+        self.0.replace(RESP_OKAY)
+    }
+}
+
 pub struct CoreBus {
+    hartid: usize,
     local_space: Space,
+    local_regions: Vec<LocalRegion>,
     sys_bus: Rc<TerminusBus>,
+    sys_map: Rc<SysMap>,
+    record: Cell<bool>,
+    accesses: RefCell<Vec<MemAccess>>,
+    pub watches: Watches,
+    //pc of the instruction stepping
+    pc: Cell<u64>,
+    trace: Rc<BusTrace>,
+    fault: Rc<BusFault>,
+    //reservation on local memory as (addr, len, who), only its own hart reaches local memory
+    local_lock: Cell<Option<(u64, usize, usize)>>,
+    //physical addresses of the halfwords of the instruction fetched in this step, consumed
+    //as they are fetched, so later accesses to the same address are data accesses
+    fetch: Cell<[Option<u64>; 2]>,
+    //address of an atomic access to a region without amo, the access faults
+    amo_fault: Cell<Option<u64>>,
 }
 
 impl CoreBus {
-    pub fn new(sys_bus: &Rc<TerminusBus>, name:String, hartid:u32, ilm_info: MemInfo, dlm_info: MemInfo) -> CoreBus {
+    pub fn new(
+        hartid: usize,
+        sys_bus: &Rc<TerminusBus>,
+        sys_map: &Rc<SysMap>,
+        trace: &Rc<BusTrace>,
+        fault: &Rc<BusFault>,
+        local_regions: Vec<LocalRegion>,
+    ) -> Result<CoreBus, String> {
+        let name = format!("core{}", hartid);
         let mut space = Space::new();
-        let ilm = Box::new(ExtBus {
-            name: format!("{}.ilm",name),
-            id: hartid << 4,
-            base: ilm_info.base,
-            size: ilm_info.size,
-        });
-        let dlm = Box::new(ExtBus {
-            name: format!("{}.dlm",name),
-            id: (hartid << 4) + 1,
-            base: dlm_info.base,
-            size: dlm_info.size,
-        });
-        space
-            .add_region("ilm", &Region::remap(ilm_info.base, &Region::io(0, ilm.size, ilm)))
-            .unwrap();
-        space
-            .add_region("dlm", &Region::remap(dlm_info.base, &Region::io(0, dlm.size, dlm)))
-            .unwrap();
-        CoreBus {
+        for r in &local_regions {
+            let mem = Box::new(ExtBus {
+                name: format!("{}.{}", name, r.name),
+                id: r.id,
+                base: r.info.base,
+                size: r.info.size,
+                mem: r.mem.clone(),
+                fault: fault.clone(),
+            });
+            space
+                .add_region(
+                    &r.name,
+                    &Region::remap(r.info.base, &Region::io(0, mem.size, mem)),
+                )
+                .map_err(|e| format!("{} add local region {} fail: {:?}!", name, r.name, e))?;
+        }
+        Ok(CoreBus {
+            hartid,
             local_space: space,
+            local_regions,
             sys_bus: sys_bus.clone(),
-        }
+            sys_map: sys_map.clone(),
+            record: Cell::new(false),
+            accesses: RefCell::new(vec![]),
+            watches: Watches::default(),
+            pc: Cell::new(0),
+            trace: trace.clone(),
+            fault: fault.clone(),
+            fetch: Cell::new([None; 2]),
+            local_lock: Cell::new(None),
+            amo_fault: Cell::new(None),
+        })
+    }
+
+    pub fn local_region(&self, name: &str) -> Option<&LocalRegion> {
+        self.local_regions.iter().find(|r| r.name == name)
+    }
+
+    pub fn local_regions(&self) -> &[LocalRegion] {
+        &self.local_regions
     }
 
     fn try_read_local(&self, addr: &u64, data: *mut u8, len: usize) -> Result<(), u64> {
@@ -48,8 +194,153 @@ impl CoreBus {
         Ok(())
     }
 
-    fn is_local(&self, addr: &u64) -> bool {
-        self.local_space.get_region_by_addr(addr).is_ok()
+    //record accesses from now on until take_record
+    pub fn start_record(&self) {
+        self.accesses.borrow_mut().clear();
+        self.record.set(true);
+    }
+
+    pub fn take_record(&self) -> Vec<MemAccess> {
+        self.record.set(false);
+        self.accesses.take()
+    }
+
+    fn record(&self, addr: &u64, size: usize, data: u64, write: bool) {
+        if self.record.get() {
+            self.accesses.borrow_mut().push(MemAccess {
+                addr: *addr,
+                size,
+                data,
+                write,
+            })
+        }
+    }
+
+    //bulk access for loaders and debuggers, neither recorded nor watched
+    pub fn write_bytes(&self, addr: &u64, data: &[u8]) -> Result<(), u64> {
+        if self.local_space.write_bytes(addr, data).is_err() {
+            self.sys_bus.space().write_bytes(addr, data)?;
+        }
+        self.check_fault(addr)
+    }
+
+    pub fn read_bytes(&self, addr: &u64, data: &mut [u8]) -> Result<(), u64> {
+        if !self.pma_at(addr).idempotent {
+            return Err(*addr);
+        }
+        if self.local_space.read_bytes(addr, data).is_err() {
+            self.sys_bus.space().read_bytes(addr, data)?;
+        }
+        self.check_fault(addr)
+    }
+
+    //an error response fails the access with its address, the hart takes an access fault
+    fn check_fault(&self, addr: &u64) -> Result<(), u64> {
+        match self.fault.take() {
+            RESP_OKAY => Ok(()),
+            _ => Err(*addr),
+        }
+    }
+
+    //`fetch` are the physical addresses of the halfwords of the instruction at `pc`, `None` if
+    //they are not known
+    pub fn set_pc(&self, pc: u64, fetch: [Option<u64>; 2]) {
+        self.pc.set(pc);
+        self.fetch.set(fetch);
+        self.amo_fault.set(None);
+    }
+
+    fn check_amo_fault(&self, addr: &u64) -> Result<(), u64> {
+        if self.amo_fault.get() == Some(*addr) {
+            self.amo_fault.set(None);
+            return Err(*addr);
+        }
+        Ok(())
+    }
+
+    fn local_region_at(&self, addr: &u64) -> Option<&LocalRegion> {
+        self.local_regions
+            .iter()
+            .find(|r| *addr >= r.info.base && *addr - r.info.base < r.info.size)
+    }
+
+    //attributes of the region at addr, unmapped addresses get the default and fail on the bus anyway
+    fn pma_at(&self, addr: &u64) -> Pma {
+        match self.local_region_at(addr) {
+            Some(r) => r.pma,
+            None => self.sys_map.find(addr, |r| r.pma).unwrap_or_default(),
+        }
+    }
+
+    fn check_pma(&self, addr: &u64, size: usize, fetch: bool) -> Result<(), u64> {
+        let pma = self.pma_at(addr);
+        let allowed = if fetch {
+            pma.executable
+        } else {
+            pma.misaligned || addr.is_multiple_of(size as u64)
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(*addr)
+        }
+    }
+
+    //atomic access, the access faults if the region has no amo, return false if it is not local
+    fn atomic(&self, addr: &u64) -> bool {
+        if !self.pma_at(addr).amo {
+            self.amo_fault.set(Some(*addr));
+        }
+        self.local_region_at(addr).is_some()
+    }
+
+    //a read of the next halfword of the instruction the hart is fetching
+    fn is_fetch(&self, addr: &u64, size: usize) -> bool {
+        match (self.fetch.get(), size) {
+            ([Some(lo), _], 2) => *addr == lo,
+            ([Some(lo), Some(hi)], 4) => *addr == lo && hi == lo + 2,
+            ([None, Some(hi)], 2) => *addr == hi,
+            _ => false,
+        }
+    }
+
+    //the fetch is done unless only the first halfword of a 32-bit instruction is fetched
+    fn fetched(&self, size: usize, data: u64) {
+        self.fetch.set(match self.fetch.get() {
+            [Some(_), hi] if size == 2 && data & 0x3 == 0x3 => [None, hi],
+            _ => [None; 2],
+        })
+    }
+
+    //watch and trace a successful access, `addr` is physical
+    fn observe(&self, addr: &u64, size: usize, data: u64, write: bool, local: bool, fetch: bool) {
+        if !self.watches.is_empty() && !fetch {
+            let kind = if write { WATCH_WRITE } else { WATCH_READ };
+            self.watches
+                .check(self.pc.get(), *addr, size as u64, data, kind)
+        }
+        if self.trace.enabled() {
+            let (route, region) = if local {
+                ("local", self.local_region_at(addr).map(|r| r.name.clone()))
+            } else {
+                ("sys", self.sys_map.find(addr, |r| r.name.clone()))
+            };
+            let rw = match (write, fetch) {
+                (true, _) => 'w',
+                (false, true) => 'x',
+                (false, false) => 'r',
+            };
+            self.trace.log(
+                self.hartid,
+                self.pc.get(),
+                route,
+                region.as_deref().unwrap_or("-"),
+                *addr,
+                size,
+                data,
+                rw,
+            )
+        }
     }
 }
 
@@ -63,9 +354,15 @@ macro_rules! corebus_add_access {
     (@write, $t:ty) => {
         paste! {
             fn [<write_ $t>](&self, addr: &u64, data: &$t) -> Result<(), u64> {
-                if self.try_write_local(addr, data as *const $t as *const u8, std::mem::size_of::<$t>()).is_err() {
+                self.check_amo_fault(addr)?;
+                self.check_pma(addr, std::mem::size_of::<$t>(), false)?;
+                let local = self.try_write_local(addr, data as *const $t as *const u8, std::mem::size_of::<$t>()).is_ok();
+                if !local {
                     self.sys_bus.[<write_ $t>](addr, data)?;
                 }
+                self.check_fault(addr)?;
+                self.record(addr, std::mem::size_of::<$t>(), *data as u64, true);
+                self.observe(addr, std::mem::size_of::<$t>(), *data as u64, true, local, false);
                 Ok(())
             }
         }
@@ -73,9 +370,23 @@ macro_rules! corebus_add_access {
     (@read, $t:ty) => {
         paste! {
             fn [<read_ $t>](&self, addr: &u64, data: &mut $t) -> Result<(), u64> {
-                if self.try_read_local(addr, data as *mut $t as *mut u8, std::mem::size_of::<$t>()).is_err() {
+                self.check_amo_fault(addr)?;
+                let fetch = self.is_fetch(addr, std::mem::size_of::<$t>());
+                if let Err(e) = self.check_pma(addr, std::mem::size_of::<$t>(), fetch) {
+                    self.fetch.set([None; 2]);
+                    return Err(e);
+                }
+                let local = self.try_read_local(addr, data as *mut $t as *mut u8, std::mem::size_of::<$t>()).is_ok();
+                if !local {
                     self.sys_bus.[<read_ $t>](addr, data)?;
                 }
+                self.check_fault(addr)?;
+                if fetch {
+                    self.fetched(std::mem::size_of::<$t>(), *data as u64);
+                } else {
+                    self.record(addr, std::mem::size_of::<$t>(), *data as u64, false);
+                }
+                self.observe(addr, std::mem::size_of::<$t>(), *data as u64, false, local, fetch);
                 Ok(())
             }
         }
@@ -84,24 +395,40 @@ macro_rules! corebus_add_access {
 
 impl Bus for CoreBus {
     fn acquire(&self, addr: &u64, len: usize, who: usize) -> bool {
-        if self.is_local(addr) {
-            panic!("acquire is not supported for local memory!")
+        if !self.atomic(addr) {
+            return self.sys_bus.acquire(addr, len, who);
+        }
+        match self.local_lock.get() {
+            Some((_, _, holder)) if holder != who => false,
+            _ => {
+                self.local_lock.set(Some((*addr, len, who)));
+                true
+            }
         }
-        self.sys_bus.acquire(addr, len, who)
     }
     fn lock_holder(&self, addr: &u64, len: usize) -> Option<usize> {
-        if self.is_local(addr) {
-            return None
+        if !self.atomic(addr) {
+            return self.sys_bus.lock_holder(addr, len);
+        }
+        match self.local_lock.get() {
+            Some((a, l, who)) if *addr < a + l as u64 && a < *addr + len as u64 => Some(who),
+            _ => None,
         }
-        self.sys_bus.lock_holder(addr, len)
     }
     fn invalid_lock(&self, addr: &u64, len: usize, who: usize) {
-        if self.is_local(addr) {
-            panic!("invalid_lock is not supported for local memory!")
+        if self.local_region_at(addr).is_none() {
+            return self.sys_bus.invalid_lock(addr, len, who);
+        }
+        if let Some((a, l, holder)) = self.local_lock.get() {
+            if holder != who && *addr < a + l as u64 && a < *addr + len as u64 {
+                self.local_lock.set(None);
+            }
         }
-        self.sys_bus.invalid_lock(addr, len, who)
     }
     fn release(&self, who: usize) {
+        if matches!(self.local_lock.get(), Some((_, _, holder)) if holder == who) {
+            self.local_lock.set(None);
+        }
         self.sys_bus.release(who)
     }
 
@@ -114,6 +441,9 @@ pub struct ExtBus {
     pub id: u32,
     pub base: u64,
     pub size: u64,
+    //accesses go to rust-owned memory instead of dpi if set
+    pub mem: Option<Arc<SparseMem>>,
+    pub fault: Rc<BusFault>,
 }
 macro_rules! extbus_add_access {
     ($($t:ty),+ ) => {
@@ -130,11 +460,16 @@ macro_rules! extbus_add_access {
         paste! {
             fn write(&self, addr: &u64, data: $t) {
                 extern "C" {
-                    fn [<cluster_ext_write_ $t>](id:u32, addr: u64, data: $t);
+                    fn [<cluster_ext_write_ $t>](id:u32, addr: u64, data: $t) -> u32;
                 }
-                unsafe {
-                    [<cluster_ext_write_ $t>](self.id, self.base + *addr as u64, data);
+                if let Some(mem) = &self.mem {
+                    mem.write(self.base + *addr, &data.to_le_bytes());
+                    return;
                 }
+                let resp = unsafe {
+                    [<cluster_ext_write_ $t>](self.id, self.base + *addr as u64, data)
+                };
+                self.fault.set(resp)
             }
         }
     };
@@ -142,13 +477,19 @@ macro_rules! extbus_add_access {
         paste! {
             fn read(&self, addr: &u64) -> $t {
                 extern "C" {
-                    fn [<cluster_ext_read_ $t>](id:u32, addr: u64, data: &mut $t);
+                    fn [<cluster_ext_read_ $t>](id:u32, addr: u64, data: &mut $t) -> u32;
                 }
-                unsafe {
-                    let mut data:$t = 0;
-                    [<cluster_ext_read_ $t>](self.id, self.base + *addr as u64, &mut data);
-                    data
+                if let Some(mem) = &self.mem {
+                    let mut bytes = [0; std::mem::size_of::<$t>()];
+                    mem.read(self.base + *addr, &mut bytes);
+                    return $t::from_le_bytes(bytes);
                 }
+                let mut data:$t = 0;
+                let resp = unsafe {
+                    [<cluster_ext_read_ $t>](self.id, self.base + *addr as u64, &mut data)
+                };
+                self.fault.set(resp);
+                data
             }
         }
     };
@@ -156,8 +497,8 @@ macro_rules! extbus_add_access {
 
 extbus_add_access!(u8, u16, u32, u64);
 
-impl BytesAccess for ExtBus {
-    fn write(&self, addr: &u64, data: &[u8]) -> Result<usize, String> {
+impl ExtBus {
+    fn write_word(&self, addr: &u64, data: &[u8]) {
         macro_rules! dispatch_to {
             ($t:ty) => {
                 paste! {
@@ -176,10 +517,9 @@ impl BytesAccess for ExtBus {
             8 => dispatch_to!(u64),
             _ => unreachable!(),
         }
-        Ok(0)
     }
 
-    fn read(&self, addr: &u64, data: &mut [u8]) -> Result<usize, String> {
+    fn read_word(&self, addr: &u64, data: &mut [u8]) {
         macro_rules! dispatch_to {
             ($t:ty) => {
                 paste! {
@@ -194,7 +534,253 @@ impl BytesAccess for ExtBus {
             8 => dispatch_to!(u64),
             _ => unreachable!(),
         }
+    }
+
+    #[cfg(not(feature = "ext_burst"))]
+    fn write_burst(&self, addr: &u64, data: &[u8]) {
+        for (offset, len) in burst_words(*addr, data.len()) {
+            self.write_word(&(*addr + offset as u64), &data[offset..offset + len])
+        }
+    }
+
+    #[cfg(not(feature = "ext_burst"))]
+    fn read_burst(&self, addr: &u64, data: &mut [u8]) {
+        for (offset, len) in burst_words(*addr, data.len()) {
+            self.read_word(&(*addr + offset as u64), &mut data[offset..offset + len])
+        }
+    }
+
+    //the whole burst is handed to sv, sv copies data with cluster_burst_get/cluster_burst_put
+    #[cfg(feature = "ext_burst")]
+    fn write_burst(&self, addr: &u64, data: &[u8]) {
+        extern "C" {
+            fn cluster_ext_write_bytes(id: u32, addr: u64, buf: *const c_void, len: u32) -> u32;
+        }
+        let resp = unsafe {
+            cluster_ext_write_bytes(
+                self.id,
+                self.base + *addr,
+                data.as_ptr() as *const c_void,
+                data.len() as u32,
+            )
+        };
+        self.fault.set(resp)
+    }
+
+    #[cfg(feature = "ext_burst")]
+    fn read_burst(&self, addr: &u64, data: &mut [u8]) {
+        extern "C" {
+            fn cluster_ext_read_bytes(id: u32, addr: u64, buf: *mut c_void, len: u32) -> u32;
+        }
+        let resp = unsafe {
+            cluster_ext_read_bytes(
+                self.id,
+                self.base + *addr,
+                data.as_mut_ptr() as *mut c_void,
+                data.len() as u32,
+            )
+        };
+        self.fault.set(resp)
+    }
+}
+
+//split [addr, addr + len) into naturally aligned accesses of at most 8 bytes, as (offset, len)
+fn burst_words(addr: u64, len: usize) -> impl Iterator<Item = (usize, usize)> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        if offset >= len {
+            return None;
+        }
+        let cur = addr + offset as u64;
+        let mut size = 8;
+        while !cur.is_multiple_of(size as u64) || size > len - offset {
+            size >>= 1;
+        }
+        let word = (offset, size);
+        offset += size;
+        Some(word)
+    })
+}
+
+impl BytesAccess for ExtBus {
+    fn write(&self, addr: &u64, data: &[u8]) -> Result<usize, String> {
+        if let Some(mem) = &self.mem {
+            mem.write(self.base + *addr, data);
+            return Ok(0);
+        }
+        match data.len() {
+            1 | 2 | 4 | 8 => self.write_word(addr, data),
+            _ => self.write_burst(addr, data),
+        }
+        Ok(0)
+    }
+
+    fn read(&self, addr: &u64, data: &mut [u8]) -> Result<usize, String> {
+        if let Some(mem) = &self.mem {
+            mem.read(self.base + *addr, data);
+            return Ok(0);
+        }
+        match data.len() {
+            1 | 2 | 4 | 8 => self.read_word(addr, data),
+            _ => self.read_burst(addr, data),
+        }
         // println!("read @{:#x}, {:#x?}", *addr, data);
         Ok(0)
     }
 }
+
+//sv side of burst transfers, `data` is an open array of byte
+#[cfg(feature = "ext_burst")]
+mod burst_dpi {
+    use std::os::raw::c_void;
+    extern "C" {
+        fn svGetArrayPtr(h: *const c_void) -> *mut c_void;
+        fn svSize(h: *const c_void, d: i32) -> i32;
+    }
+
+    //copy write burst data of `len` bytes to sv, sv arrays of another size are only partly copied
+    #[no_mangle]
+    unsafe extern "C" fn cluster_burst_get(buf: *const c_void, len: u32, data: *const c_void) {
+        std::ptr::copy_nonoverlapping(
+            buf as *const u8,
+            svGetArrayPtr(data) as *mut u8,
+            burst_len(len, data),
+        )
+    }
+
+    //copy read burst data of `len` bytes from sv
+    #[no_mangle]
+    unsafe extern "C" fn cluster_burst_put(buf: *mut c_void, len: u32, data: *const c_void) {
+        std::ptr::copy_nonoverlapping(
+            svGetArrayPtr(data) as *const u8,
+            buf as *mut u8,
+            burst_len(len, data),
+        )
+    }
+
+    unsafe fn burst_len(len: u32, data: *const c_void) -> usize {
+        std::cmp::min(len as usize, svSize(data, 1).max(0) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sys_map() -> SysMap {
+        let map = SysMap::default();
+        map.add(SysRegion {
+            name: "clint".to_string(),
+            info: MemInfo {
+                base: 0x0200_0000,
+                size: 0x000c_0000,
+            },
+            pma: Pma::io(),
+        });
+        map
+    }
+
+    #[test]
+    fn sys_region_ok() {
+        let map = sys_map();
+        let adjacent = |base, size| MemInfo { base, size };
+        assert!(map.check("below", &adjacent(0x01ff_0000, 0x1_0000)).is_ok());
+        assert!(map.check("above", &adjacent(0x020c_0000, 0x1000)).is_ok());
+    }
+
+    #[test]
+    fn sys_region_empty() {
+        let err = sys_map()
+            .check(
+                "rom",
+                &MemInfo {
+                    base: 0x1000,
+                    size: 0,
+                },
+            )
+            .unwrap_err();
+        assert!(err.contains("empty"));
+    }
+
+    #[test]
+    fn sys_region_duplicate() {
+        let err = sys_map()
+            .check(
+                "clint",
+                &MemInfo {
+                    base: 0x1000,
+                    size: 0x1000,
+                },
+            )
+            .unwrap_err();
+        assert!(err.contains("already exists"));
+    }
+
+    #[test]
+    fn sys_region_overlap() {
+        let map = sys_map();
+        for (base, size) in [
+            (0x01ff_f000, 0x2000),
+            (0x020b_f000, 0x2000),
+            (0x0201_0000, 0x10),
+            (0x0100_0000, 0x0200_0000),
+        ] {
+            let err = map.check("plic", &MemInfo { base, size }).unwrap_err();
+            assert!(err.contains("overlaps with clint"));
+        }
+    }
+
+    #[test]
+    fn sys_region_wraps() {
+        let err = sys_map()
+            .check(
+                "top",
+                &MemInfo {
+                    base: u64::MAX - 0xfff,
+                    size: 0x2000,
+                },
+            )
+            .unwrap_err();
+        assert!(err.contains("exceeds address space"));
+    }
+
+    #[test]
+    fn burst_aligned() {
+        assert_eq!(
+            burst_words(0x1000, 16).collect::<Vec<_>>(),
+            vec![(0, 8), (8, 8)]
+        );
+    }
+
+    #[test]
+    fn burst_unaligned_head() {
+        assert_eq!(
+            burst_words(0x1003, 13).collect::<Vec<_>>(),
+            vec![(0, 1), (1, 4), (5, 8)]
+        );
+    }
+
+    #[test]
+    fn burst_odd_tail() {
+        assert_eq!(
+            burst_words(0x1000, 15).collect::<Vec<_>>(),
+            vec![(0, 8), (8, 4), (12, 2), (14, 1)]
+        );
+    }
+
+    #[test]
+    fn burst_words_cover_data() {
+        for addr in 0x1000..0x1008 {
+            for len in 0..24 {
+                let mut next = 0;
+                for (offset, size) in burst_words(addr, len) {
+                    assert_eq!(offset, next);
+                    assert!([1, 2, 4, 8].contains(&size));
+                    assert!((addr + offset as u64).is_multiple_of(size as u64));
+                    next += size;
+                }
+                assert_eq!(next, len);
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+//csrs saved in checkpoints in restore order, csrs not implemented by a hart are skipped
+#[rustfmt::skip]
+pub const CHECKPOINT_CSRS: &[u32] = &[
+    //mstatus, sstatus is a view of it
+    0x300,
+    //sie, stvec, scounteren, sscratch, sepc, scause, stval, sip, satp
+    0x104, 0x105, 0x106, 0x140, 0x141, 0x142, 0x143, 0x144, 0x180,
+    //medeleg, mideleg, mie, mtvec, mcounteren, mscratch, mepc, mcause, mtval, mip
+    0x302, 0x303, 0x304, 0x305, 0x306, 0x340, 0x341, 0x342, 0x343, 0x344,
+    //pmpcfg0-3, pmpaddr0-15
+    0x3a0, 0x3a1, 0x3a2, 0x3a3,
+    0x3b0, 0x3b1, 0x3b2, 0x3b3, 0x3b4, 0x3b5, 0x3b6, 0x3b7,
+    0x3b8, 0x3b9, 0x3ba, 0x3bb, 0x3bc, 0x3bd, 0x3be, 0x3bf,
+    //mcycle, minstret
+    0xb00, 0xb02,
+];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HartCheckpoint {
+    pub state: u32,
+    //parked in wfi
+    pub wfi: bool,
+    pub privilege: u8,
+    pub pc: u64,
+    pub xregs: Vec<u64>,
+    pub csrs: Vec<(u32, u64)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClintCheckpoint {
+    pub mtime: u64,
+    pub msip: Vec<u32>,
+    pub mtimecmp: Vec<u64>,
+}
+
+//registers of the plic in the standard layout, one context per hart,
+//pending bits follow the irq lines and claims in progress are not saved
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlicCheckpoint {
+    //priority of sources from 1
+    pub priority: Vec<u32>,
+    //enable words of each context
+    pub enable: Vec<Vec<u32>>,
+    pub threshold: Vec<u32>,
+}
+
+//pages of a sparse memory, dpi memories are saved by sv
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MemCheckpoint {
+    pub id: u32,
+    pub pages: Vec<(u64, Vec<u8>)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Checkpoint {
+    pub steps: u64,
+    //free mtime mode bookkeeping, see Cluster
+    pub mtime_ticks: u64,
+    pub hart_steps: Vec<u64>,
+    pub harts: Vec<HartCheckpoint>,
+    pub clint: ClintCheckpoint,
+    pub plic: PlicCheckpoint,
+    pub mems: Vec<MemCheckpoint>,
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("create {} fail: {}!", path, e))?;
+        bincode::serialize_into(BufWriter::new(file), self)
+            .map_err(|e| format!("write {} fail: {}!", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Checkpoint, String> {
+        let file = File::open(path).map_err(|e| format!("open {} fail: {}!", path, e))?;
+        bincode::deserialize_from(BufReader::new(file))
+            .map_err(|e| format!("read {} fail: {}!", path, e))
+    }
+}
+
+//sv saves or restores its own memories alongside the checkpoint at `path`
+#[cfg(feature = "sv_checkpoint")]
+pub fn sv_checkpoint(handle: u32, path: &str, save: bool) {
+    extern "C" {
+        fn cluster_sv_checkpoint(handle: u32, path: *const std::os::raw::c_char, save: u32);
+    }
+    let path = std::ffi::CString::new(path).unwrap();
+    unsafe { cluster_sv_checkpoint(handle, path.as_ptr(), save as u32) }
+}
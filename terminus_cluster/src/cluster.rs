@@ -0,0 +1,1137 @@
+use crate::bus::{BusFault, CoreBus, ExtBus, SysMap, SysRegion};
+use crate::checkpoint::{
+    Checkpoint, ClintCheckpoint, HartCheckpoint, MemCheckpoint, PlicCheckpoint, CHECKPOINT_CSRS,
+};
+use crate::commit_log::CommitLog;
+use crate::config::{
+    ClintDesc, ClusterCfg, ExtWindowDesc, MtimeMode, PlicDesc, Pma, ProcessorDesc, WatchDesc,
+};
+use crate::elf::ElfImage;
+use crate::gdb::GdbServer;
+use crate::lockstep::{Lockstep, LockstepStatus, RtlRetire};
+use crate::mem::SparseMem;
+use crate::retire::{RetireProbe, Retired};
+use crate::rvfi::RvfiRing;
+use crate::stats::{ClusterStats, HartStats};
+use crate::trace::BusTrace;
+use crate::watch::{parse_kinds, WatchHit, Watchpoint, WATCH_EXEC};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use terminus::devices::bus::{Bus, TerminusBus};
+use terminus::devices::clint::*;
+use terminus::devices::plic::*;
+use terminus::global::{RegT, XLen};
+use terminus::memory::{region::*, MemInfo};
+use terminus::processor::{Privilege, Processor};
+
+pub struct Cluster {
+    pub processors: Vec<Processor>,
+    pub core_buses: Vec<Rc<CoreBus>>,
+    pub hart_descs: Vec<ProcessorDesc>,
+    sys_bus: Rc<TerminusBus>,
+    sys_regions: Rc<SysMap>,
+    trace: Rc<BusTrace>,
+    fault: Rc<BusFault>,
+    ext_windows: Vec<ExtWindowDesc>,
+    irq_lines: Vec<IrqVecSender>,
+    clint: Rc<Timer>,
+    clint_desc: ClintDesc,
+    plic_desc: PlicDesc,
+    //mtime ticks issued in free mtime mode, mtime follows whichever clock is furthest ahead:
+    //`steps` cluster steps or the per-hart steps of a hart
+    mtime_ticks: u64,
+    //steps of each hart by cluster_step_core, one cycle at its own freq
+    hart_steps: Vec<u64>,
+    //a cluster step is one cycle of the fastest hart
+    hart_freq: u64,
+    steps: u64,
+    mems: Vec<(u32, Arc<SparseMem>)>,
+    hart_states: Vec<HartState>,
+    //restored parked in wfi, which can't be set in the iss, so the hart waits here instead
+    wfi_restored: Vec<bool>,
+    commit_logs: Vec<CommitLog>,
+    rvfi: Vec<RvfiRing>,
+    lockstep: Vec<Option<Lockstep>>,
+    stats: Vec<HartStats>,
+    symbols: HashMap<String, (u64, u64)>,
+    watch_id: u32,
+    //watchpoints on symbols not loaded yet
+    pending_watches: Vec<WatchDesc>,
+    //hits to report to sv once the cluster is released, as (hartid, hit)
+    watch_reports: Vec<(usize, WatchHit)>,
+    gdb: Option<GdbServer>,
+    skip_wfi: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(u32)]
+pub enum HartState {
+    //held in reset, released by reset_core or resume_core
+    Reset = 0,
+    Running = 1,
+    //paused by halt_core, resumed by resume_core without reset
+    Halted = 2,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(u32)]
+pub enum StepStatus {
+    //all requested instructions are stepped
+    Exhausted = 0,
+    //hart is waiting for interrupt
+    Wfi = 1,
+    //the last step did not retire an instruction but took a trap
+    Trap = 2,
+    //hart is not running
+    Halted = 3,
+}
+
+impl Cluster {
+    pub fn new(cfg: &ClusterCfg) -> Result<Cluster, String> {
+        let sys_bus = Rc::new(TerminusBus::new());
+        let clint = Rc::new(Timer::new(cfg.devices.clint.freq));
+        let hart_descs = cfg.hart_descs();
+        cfg.check_harts()?;
+        let mut cluster = Cluster {
+            processors: vec![],
+            core_buses: vec![],
+            hart_descs: hart_descs.clone(),
+            sys_bus: sys_bus.clone(),
+            sys_regions: Rc::new(SysMap::default()),
+            trace: Rc::new(BusTrace::new()),
+            fault: Rc::new(BusFault::default()),
+            ext_windows: vec![],
+            irq_lines: vec![],
+            clint: clint.clone(),
+            clint_desc: cfg.devices.clint.clone(),
+            plic_desc: cfg.devices.plic.clone(),
+            mtime_ticks: 0,
+            hart_steps: vec![0; hart_descs.len()],
+            hart_freq: hart_descs
+                .iter()
+                .map(|d| d.freq as u64)
+                .max()
+                .unwrap_or(1)
+                .max(1),
+            steps: 0,
+            mems: vec![],
+            hart_states: vec![HartState::Reset; hart_descs.len()],
+            wfi_restored: vec![false; hart_descs.len()],
+            commit_logs: hart_descs
+                .iter()
+                .enumerate()
+                .map(|(hartid, desc)| {
+                    CommitLog::new(format!("{}{}.log", cfg.commit_log, hartid), desc.xlen)
+                })
+                .collect(),
+            rvfi: (0..hart_descs.len())
+                .map(|_| RvfiRing::new(cfg.rvfi_depth))
+                .collect(),
+            lockstep: (0..hart_descs.len()).map(|_| None).collect(),
+            stats: (0..hart_descs.len()).map(HartStats::new).collect(),
+            symbols: HashMap::new(),
+            watch_id: 0,
+            pending_watches: vec![],
+            watch_reports: vec![],
+            gdb: None,
+            skip_wfi: cfg.skip_wfi,
+        };
+        for w in &cfg.ext_windows {
+            cluster.add_ext_window(w)?;
+        }
+        cluster.add_sys_region(
+            "clint",
+            cfg.devices.clint.info(),
+            Pma::io(),
+            &Region::io(0, cfg.devices.clint.size, Box::new(Clint::new(&clint))),
+        )?;
+        let intc = Rc::new(Intc::new());
+        cluster.add_sys_region(
+            "plic",
+            cfg.devices.plic.info(),
+            Pma::io(),
+            &Region::io(0, cfg.devices.plic.size, Box::new(Plic::new(&intc))),
+        )?;
+        cluster.irq_lines = (1..=cfg.devices.plic.num_sources as usize)
+            .map(|src| intc.alloc_src(src))
+            .collect();
+        for (hartid, desc) in hart_descs.iter().enumerate() {
+            let core_bus = Rc::new(CoreBus::new(
+                hartid,
+                &sys_bus,
+                &cluster.sys_regions,
+                &cluster.trace,
+                &cluster.fault,
+                desc.local_regions(hartid),
+            )?);
+            for (i, r) in core_bus.local_regions().iter().enumerate() {
+                let name = format!("local region core{}.{}", hartid, r.name);
+                cluster.check_region_id(r.id, &name)?;
+                if let Some(o) = core_bus.local_regions()[..i].iter().find(|o| o.id == r.id) {
+                    return Err(format!(
+                        "{} id {:#x} is already used by core{}.{}!",
+                        name, r.id, hartid, o.name
+                    ));
+                }
+            }
+            cluster.processors.push(Processor::new(
+                hartid,
+                desc.to_cfg()
+                    .map_err(|e| format!("core{} config error: {}", hartid, e))?,
+                &core_bus,
+                Some(clint.alloc_irq()),
+                Some(intc.alloc_irq()),
+            ));
+            for r in core_bus.local_regions() {
+                if let Some(mem) = &r.mem {
+                    cluster.mems.push((r.id, mem.clone()));
+                }
+            }
+            cluster.core_buses.push(core_bus);
+        }
+        for w in &cfg.watchpoints {
+            if w.symbol.is_some() && w.base.is_none() {
+                cluster.pending_watches.push(w.clone());
+            } else {
+                cluster.add_watch_desc(w)?;
+            }
+        }
+        if !cfg.bus_trace.is_empty() {
+            cluster.trace.start(&cfg.bus_trace)?;
+        }
+        if cfg.gdb.port != 0 {
+            cluster.gdb_listen(cfg.gdb.port, cfg.gdb.wait)?;
+        }
+        Ok(cluster)
+    }
+
+    fn add_sys_region(
+        &mut self,
+        name: &str,
+        info: MemInfo,
+        pma: Pma,
+        region: &Rc<Region>,
+    ) -> Result<(), String> {
+        self.sys_regions.check(name, &info)?;
+        self.sys_bus
+            .space_mut()
+            .add_region(name, &Region::remap(info.base, region))
+            .map_err(|e| format!("add region {} fail: {:?}!", name, e))?;
+        self.sys_regions.add(SysRegion {
+            name: name.to_string(),
+            info,
+            pma,
+        });
+        Ok(())
+    }
+
+    //dpi ids of ext windows and local regions select memories, so they must be unique
+    fn check_region_id(&self, id: u32, name: &str) -> Result<(), String> {
+        let used = self
+            .ext_windows
+            .iter()
+            .map(|w| (w.id, w.name.clone()))
+            .chain(self.core_buses.iter().enumerate().flat_map(|(hartid, b)| {
+                b.local_regions()
+                    .iter()
+                    .map(move |r| (r.id, format!("core{}.{}", hartid, r.name)))
+            }))
+            .find(|(i, _)| *i == id);
+        match used {
+            Some((_, user)) => Err(format!(
+                "{} id {:#x} is already used by {}!",
+                name, id, user
+            )),
+            None => Ok(()),
+        }
+    }
+
+    //sparse memories as (dpi id, memory)
+    pub fn mems(&self) -> &[(u32, Arc<SparseMem>)] {
+        &self.mems
+    }
+
+    pub fn tick_mtime(&self, n: u64) -> Result<(), String> {
+        if self.clint_desc.mtime != MtimeMode::Sim {
+            return Err("tick mtime is only valid in sim mtime mode!".to_string());
+        }
+        self.clint.tick(n);
+        Ok(())
+    }
+
+    //free mode: clint cycles elapsed in `steps` cluster steps at clint freq,
+    //sim mode: one tick every `steps_per_tick` cluster steps
+    fn advance_mtime(&mut self, steps: u64) {
+        let ticks = match self.clint_desc.mtime {
+            MtimeMode::Free => {
+                let ticks = clint_ticks(self.steps + steps, self.clint_desc.freq, self.hart_freq);
+                self.catch_up_mtime(ticks)
+            }
+            MtimeMode::Sim if self.clint_desc.steps_per_tick != 0 => {
+                (self.steps + steps) / self.clint_desc.steps_per_tick
+                    - self.steps / self.clint_desc.steps_per_tick
+            }
+            MtimeMode::Sim => 0,
+        };
+        self.steps += steps;
+        if ticks != 0 {
+            self.clint.tick(ticks)
+        }
+    }
+
+    //free mode: clint cycles elapsed in the steps of hart `hartid` at its own freq,
+    //sim mode: per-hart steps do not count for `steps_per_tick`
+    fn advance_hart_mtime(&mut self, hartid: usize, steps: u64) {
+        if self.clint_desc.mtime != MtimeMode::Free {
+            return;
+        }
+        self.hart_steps[hartid] += steps;
+        let freq = self.hart_descs[hartid].freq.max(1) as u64;
+        let ticks = self.catch_up_mtime(clint_ticks(
+            self.hart_steps[hartid],
+            self.clint_desc.freq,
+            freq,
+        ));
+        if ticks != 0 {
+            self.clint.tick(ticks)
+        }
+    }
+
+    //ticks to bring mtime to `target` ticks since creation, 0 if it is already there
+    fn catch_up_mtime(&mut self, target: u64) -> u64 {
+        let ticks = target.saturating_sub(self.mtime_ticks);
+        self.mtime_ticks += ticks;
+        ticks
+    }
+
+    fn step_once(&mut self, hartid: usize) -> StepStatus {
+        if let Some(mut gdb) = self.gdb.take() {
+            gdb.poll(self, hartid);
+            self.gdb = Some(gdb);
+        }
+        //shadow harts are only stepped by lockstep checks
+        if self.hart_states[hartid] != HartState::Running || self.lockstep[hartid].is_some() {
+            return StepStatus::Halted;
+        }
+        self.step_hart(hartid, false).0
+    }
+
+    fn step_hart(&mut self, hartid: usize, observe: bool) -> (StepStatus, Option<Retired>) {
+        let p = &mut self.processors[hartid];
+        let privilege = *p.state().privilege() as u8;
+        if self.wfi_restored[hartid] {
+            if !irq_pending(p) {
+                self.stats[hartid].wfi(privilege);
+                return (StepStatus::Wfi, None);
+            }
+            self.wfi_restored[hartid] = false;
+        }
+        if self.skip_wfi && p.state().wfi() && !irq_pending(p) {
+            self.stats[hartid].wfi(privilege);
+            return (StepStatus::Wfi, None);
+        }
+        let retired = p.state().insns_cnt();
+        let bus = &self.core_buses[hartid];
+        let pc = *p.state().pc();
+        let watch = !bus.watches.is_empty();
+        bus.set_pc(pc, fetch_addrs(p, pc));
+        let probe = if observe || self.commit_logs[hartid].enabled() || self.rvfi[hartid].enabled()
+        {
+            Some(RetireProbe::before(p, bus))
+        } else {
+            None
+        };
+        p.step(1);
+        let r = probe.map(|probe| probe.after(p, bus));
+        if let Some(r) = &r {
+            self.commit_logs[hartid]
+                .log(r)
+                .unwrap_or_else(|e| panic!("{}", e));
+            self.rvfi[hartid].push(r);
+        }
+        let status = if p.state().wfi() {
+            StepStatus::Wfi
+        } else if p.state().insns_cnt() == retired {
+            StepStatus::Trap
+        } else {
+            StepStatus::Exhausted
+        };
+        self.stats[hartid].step(
+            privilege,
+            p.state().insns_cnt() - retired,
+            status == StepStatus::Trap,
+        );
+        //exec watchpoints match the virtual pc, read/write watchpoints the physical address
+        if watch {
+            let xlen = self.hart_descs[hartid].xlen;
+            if let Some(insn) = executed_insn(p, pc, status == StepStatus::Trap, xlen) {
+                bus.watches.check(pc, pc, 1, insn, WATCH_EXEC);
+            }
+            self.watch_hits(hartid);
+        }
+        (status, r)
+    }
+
+    //step a running hart by the debugger, regardless of lockstep
+    pub fn debug_step(&mut self, hartid: usize) -> Result<StepStatus, String> {
+        if self.hart_state(hartid)? != HartState::Running {
+            return Err(format!("core{} is not running!", hartid));
+        }
+        Ok(self.step_hart(hartid, false).0)
+    }
+
+    pub fn gdb_listen(&mut self, port: u16, wait: bool) -> Result<(), String> {
+        if self.gdb.is_some() {
+            return Err("gdb server is already listening!".to_string());
+        }
+        self.gdb = Some(GdbServer::listen(port, wait)?);
+        Ok(())
+    }
+
+    //`depth` preceding instructions are reported at divergence
+    pub fn enable_lockstep(&mut self, hartid: usize, depth: usize) -> Result<(), String> {
+        self.processor(hartid)?;
+        let xlen = self.hart_descs[hartid].xlen;
+        self.lockstep[hartid] = Some(Lockstep::new(xlen, depth));
+        Ok(())
+    }
+
+    pub fn disable_lockstep(&mut self, hartid: usize) -> Result<(), String> {
+        self.processor(hartid)?;
+        self.lockstep[hartid] = None;
+        Ok(())
+    }
+
+    //step shadow hart `hartid` until it retires one instruction and compare it with `rtl`
+    pub fn lockstep_check(
+        &mut self,
+        hartid: usize,
+        rtl: &RtlRetire,
+    ) -> Result<LockstepStatus, String> {
+        match self.lockstep.get(hartid) {
+            None => return Err(format!("invalid hartid {}!", hartid)),
+            Some(None) => return Err(format!("core{} is not in lockstep mode!", hartid)),
+            Some(Some(l)) if l.diverged() => return Ok(LockstepStatus::Diverged),
+            _ => {}
+        }
+        if self.hart_states[hartid] != HartState::Running {
+            return Err(format!("shadow core{} is not running!", hartid));
+        }
+        //traps and interrupts do not retire, the rtl core reports the next retired instruction,
+        //a shadow waiting in wfi never retires, interrupts of the rtl core are not injected
+        for _ in 0..LOCKSTEP_MAX_TRAPS {
+            if let (_, Some(r)) = self.step_hart(hartid, true) {
+                if !r.trap {
+                    return Ok(self.lockstep[hartid].as_mut().unwrap().check(&r, rtl));
+                }
+            }
+        }
+        Ok(self.lockstep[hartid]
+            .as_mut()
+            .unwrap()
+            .stall(hartid, LOCKSTEP_MAX_TRAPS))
+    }
+
+    //a hart stepped on its own clock moves mtime by its steps
+    pub fn step_core(&mut self, hartid: usize, n: u64) -> Result<StepStatus, String> {
+        if hartid >= self.processors.len() {
+            return Err(format!("invalid hartid {}!", hartid));
+        }
+        let (status, steps) = self.step_hart_n(hartid, n);
+        self.advance_hart_mtime(hartid, steps);
+        Ok(status)
+    }
+
+    //return why the hart stops and the steps taken, including the one it stops at
+    fn step_hart_n(&mut self, hartid: usize, n: u64) -> (StepStatus, u64) {
+        for i in 0..n {
+            match self.step_once(hartid) {
+                StepStatus::Exhausted => {}
+                s => return (s, i + 1),
+            }
+        }
+        (StepStatus::Exhausted, n)
+    }
+
+    //step every hart by `n`, return bitmask of harts stopped before `n` instructions
+    pub fn step_cores(&mut self, n: u64) -> u64 {
+        let mask = (0..self.processors.len()).fold(0, |mask, hartid| {
+            match self.step_hart_n(hartid, n).0 {
+                StepStatus::Exhausted => mask,
+                _ => mask | (1 << hartid),
+            }
+        });
+        self.advance_mtime(n);
+        mask
+    }
+
+    pub fn step_all(&mut self) {
+        for hartid in 0..self.processors.len() {
+            self.step_once(hartid);
+        }
+        self.advance_mtime(1)
+    }
+
+    pub fn set_irq_line(&self, src: u32, level: bool) -> Result<(), String> {
+        let line = src
+            .checked_sub(1)
+            .and_then(|i| self.irq_lines.get(i as usize))
+            .ok_or(format!("invalid irq source {}!", src))?;
+        let result = if level { line.send() } else { line.clear() };
+        result.map_err(|e| format!("set irq source {} fail: {}", src, e))
+    }
+
+    pub fn reset_core(&mut self, hartid: usize, boot_addr: u64) -> Result<(), String> {
+        self.processor_mut(hartid)?
+            .reset(boot_addr)
+            .map_err(|e| format!("reset core {} to {:#x} fail: {}!", hartid, boot_addr, e))?;
+        self.hart_states[hartid] = HartState::Running;
+        self.wfi_restored[hartid] = false;
+        Ok(())
+    }
+
+    pub fn enable_commit_log(&mut self, hartid: usize, en: bool) -> Result<(), String> {
+        self.commit_logs
+            .get_mut(hartid)
+            .ok_or(format!("invalid hartid {}!", hartid))?
+            .enable(en)
+    }
+
+    pub fn rvfi(&mut self, hartid: usize) -> Result<&mut RvfiRing, String> {
+        self.rvfi
+            .get_mut(hartid)
+            .ok_or(format!("invalid hartid {}!", hartid))
+    }
+
+    fn processor(&self, hartid: usize) -> Result<&Processor, String> {
+        self.processors
+            .get(hartid)
+            .ok_or(format!("invalid hartid {}!", hartid))
+    }
+
+    fn processor_mut(&mut self, hartid: usize) -> Result<&mut Processor, String> {
+        self.processors
+            .get_mut(hartid)
+            .ok_or(format!("invalid hartid {}!", hartid))
+    }
+
+    pub fn read_gpr(&self, hartid: usize, idx: u32) -> Result<RegT, String> {
+        if idx >= 32 {
+            return Err(format!("invalid gpr x{}!", idx));
+        }
+        Ok(*self.processor(hartid)?.state().xreg(idx as RegT))
+    }
+
+    //writing x0 is ignored
+    pub fn write_gpr(&mut self, hartid: usize, idx: u32, value: RegT) -> Result<(), String> {
+        if idx >= 32 {
+            return Err(format!("invalid gpr x{}!", idx));
+        }
+        if idx != 0 {
+            self.processor_mut(hartid)?
+                .state_mut()
+                .set_xreg(idx as RegT, value);
+        }
+        Ok(())
+    }
+
+    pub fn read_pc(&self, hartid: usize) -> Result<RegT, String> {
+        Ok(*self.processor(hartid)?.state().pc())
+    }
+
+    pub fn write_pc(&mut self, hartid: usize, pc: RegT) -> Result<(), String> {
+        self.processor_mut(hartid)?.state_mut().set_pc(pc);
+        Ok(())
+    }
+
+    pub fn read_csr(&self, hartid: usize, csr: u32) -> Result<RegT, String> {
+        self.processor(hartid)?
+            .state()
+            .csr(csr as RegT)
+            .map_err(|e| format!("core{} read csr {:#x} fail: {:?}!", hartid, csr, e))
+    }
+
+    pub fn write_csr(&mut self, hartid: usize, csr: u32, value: RegT) -> Result<(), String> {
+        self.processor_mut(hartid)?
+            .state_mut()
+            .set_csr(csr as RegT, value)
+            .map_err(|e| format!("core{} write csr {:#x} fail: {:?}!", hartid, csr, e))
+    }
+
+    pub fn hart_state(&self, hartid: usize) -> Result<HartState, String> {
+        self.hart_states
+            .get(hartid)
+            .copied()
+            .ok_or(format!("invalid hartid {}!", hartid))
+    }
+
+    pub fn halt_core(&mut self, hartid: usize) -> Result<(), String> {
+        if self.hart_state(hartid)? == HartState::Running {
+            self.hart_states[hartid] = HartState::Halted;
+        }
+        Ok(())
+    }
+
+    //a hart held in reset is released from its reset vector
+    pub fn resume_core(&mut self, hartid: usize) -> Result<(), String> {
+        match self.hart_state(hartid)? {
+            HartState::Reset => self.reset_core(hartid, self.hart_descs[hartid].reset_vector),
+            _ => {
+                self.hart_states[hartid] = HartState::Running;
+                Ok(())
+            }
+        }
+    }
+
+    //load segments through the bus of every hart in `hartmask`, so local memories of each hart
+    //get their own copy, return the entry point
+    pub fn load_elf(&mut self, path: &str, hartmask: u64) -> Result<u64, String> {
+        let image = ElfImage::load(path)?;
+        for (hartid, bus) in self.core_buses.iter().enumerate() {
+            if hartmask & (1 << hartid) == 0 {
+                continue;
+            }
+            for seg in &image.segments {
+                let mut data = seg.data.clone();
+                data.resize(seg.mem_size as usize, 0);
+                bus.write_bytes(&seg.addr, &data).map_err(|e| {
+                    format!(
+                        "core{} load {} segment {:#x} fail at {:#x}!",
+                        hartid, path, seg.addr, e
+                    )
+                })?;
+            }
+        }
+        self.symbols.extend(image.symbols);
+        let (resolved, pending) = self
+            .pending_watches
+            .drain(..)
+            .partition::<Vec<_>, _>(|w| self.symbols.contains_key(w.symbol.as_ref().unwrap()));
+        self.pending_watches = pending;
+        for w in &resolved {
+            self.add_watch_desc(w)?;
+        }
+        Ok(image.entry)
+    }
+
+    //symbols of all loaded elf files, the latest wins
+    pub fn symbol_addr(&self, name: &str) -> Result<u64, String> {
+        self.symbol(name).map(|(addr, _)| addr)
+    }
+
+    fn symbol(&self, name: &str) -> Result<(u64, u64), String> {
+        self.symbols
+            .get(name)
+            .copied()
+            .ok_or(format!("symbol {} is not found!", name))
+    }
+
+    //watch [base, base + size) on harts in `hartmask`, return the watchpoint id
+    pub fn add_watch(
+        &mut self,
+        hartmask: u64,
+        base: u64,
+        size: u64,
+        kinds: u32,
+        halt: bool,
+    ) -> Result<u32, String> {
+        if size == 0 || kinds == 0 {
+            return Err(format!(
+                "empty watchpoint at {:#x}, size {:#x}, kinds {:#x}!",
+                base, size, kinds
+            ));
+        }
+        let w = Watchpoint {
+            id: self.watch_id,
+            base,
+            size,
+            kinds,
+            halt,
+        };
+        for (hartid, bus) in self.core_buses.iter().enumerate() {
+            if hartmask & (1 << hartid) != 0 {
+                bus.watches.add(w);
+            }
+        }
+        self.watch_id += 1;
+        Ok(w.id)
+    }
+
+    //`size` 0 means the size of the symbol
+    pub fn add_watch_symbol(
+        &mut self,
+        hartmask: u64,
+        name: &str,
+        size: u64,
+        kinds: u32,
+        halt: bool,
+    ) -> Result<u32, String> {
+        let (addr, sym_size) = self.symbol(name)?;
+        let size = if size == 0 {
+            std::cmp::max(sym_size, 1)
+        } else {
+            size
+        };
+        self.add_watch(hartmask, addr, size, kinds, halt)
+    }
+
+    //hart ids of `w` are checked by ClusterCfg::check_harts
+    fn add_watch_desc(&mut self, w: &WatchDesc) -> Result<u32, String> {
+        let hartmask = if w.harts.is_empty() {
+            u64::MAX
+        } else {
+            w.harts.iter().fold(0, |m, h| m | (1 << h))
+        };
+        let kinds = parse_kinds(&w.kinds)?;
+        match (&w.base, &w.symbol) {
+            (Some(base), _) => self.add_watch(hartmask, *base, w.size, kinds, w.halt),
+            (None, Some(name)) => self.add_watch_symbol(hartmask, name, w.size, kinds, w.halt),
+            _ => Err("watchpoint needs base or symbol!".to_string()),
+        }
+    }
+
+    pub fn start_bus_trace(&self, path: &str) -> Result<(), String> {
+        self.trace.start(path)
+    }
+
+    pub fn stop_bus_trace(&self) {
+        self.trace.stop()
+    }
+
+    pub fn remove_watch(&mut self, id: u32) {
+        for bus in &self.core_buses {
+            bus.watches.remove(id)
+        }
+    }
+
+    fn watch_hits(&mut self, hartid: usize) {
+        for hit in self.core_buses[hartid].watches.take_hits() {
+            #[cfg(feature = "watch_callback")]
+            self.watch_reports.push((hartid, hit));
+            #[cfg(not(feature = "watch_callback"))]
+            eprintln!(
+                "watch{}: core{} pc {:#x} {} {:#x} = {:#x}",
+                hit.id,
+                hartid,
+                hit.pc,
+                hit.kind_str(),
+                hit.addr,
+                hit.data
+            );
+            if hit.halt && self.hart_states[hartid] == HartState::Running {
+                self.hart_states[hartid] = HartState::Halted;
+            }
+        }
+    }
+
+    pub fn take_watch_reports(&mut self) -> Vec<(usize, WatchHit)> {
+        std::mem::take(&mut self.watch_reports)
+    }
+
+    fn clint_read(&self, offset: u64) -> Result<u64, String> {
+        let addr = self.clint_desc.base + offset;
+        let mut data = 0;
+        self.sys_bus
+            .read_u64(&addr, &mut data)
+            .map_err(|e| format!("read clint {:#x} fail at {:#x}!", addr, e))?;
+        Ok(data)
+    }
+
+    fn clint_write(&self, offset: u64, data: u64) -> Result<(), String> {
+        let addr = self.clint_desc.base + offset;
+        self.sys_bus
+            .write_u64(&addr, &data)
+            .map_err(|e| format!("write clint {:#x} fail at {:#x}!", addr, e))
+    }
+
+    fn plic_read(&self, offset: u64) -> Result<u32, String> {
+        let addr = self.plic_desc.base + offset;
+        let mut data = 0;
+        self.sys_bus
+            .read_u32(&addr, &mut data)
+            .map_err(|e| format!("read plic {:#x} fail at {:#x}!", addr, e))?;
+        Ok(data)
+    }
+
+    fn plic_write(&self, offset: u64, data: u32) -> Result<(), String> {
+        let addr = self.plic_desc.base + offset;
+        self.sys_bus
+            .write_u32(&addr, &data)
+            .map_err(|e| format!("write plic {:#x} fail at {:#x}!", addr, e))
+    }
+
+    //enable words of a plic context, source 0 included
+    fn plic_enable_words(&self) -> usize {
+        self.plic_desc.num_sources as usize / 32 + 1
+    }
+
+    fn save_hart_csrs(&self, hartid: usize) -> Vec<(u32, u64)> {
+        let s = self.processors[hartid].state();
+        CHECKPOINT_CSRS
+            .iter()
+            .filter_map(|&csr| s.csr(csr as RegT).ok().map(|v| (csr, v)))
+            .collect()
+    }
+
+    fn save_clint(&self) -> Result<ClintCheckpoint, String> {
+        let harts_num = self.processors.len() as u64;
+        Ok(ClintCheckpoint {
+            mtime: self.clint_read(CLINT_MTIME)?,
+            msip: (0..harts_num)
+                .map(|h| self.clint_read(CLINT_MSIP + h * 4).map(|v| v as u32))
+                .collect::<Result<_, _>>()?,
+            mtimecmp: (0..harts_num)
+                .map(|h| self.clint_read(CLINT_MTIMECMP + h * 8))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn save_plic(&self) -> Result<PlicCheckpoint, String> {
+        let contexts = self.processors.len() as u64;
+        let words = self.plic_enable_words() as u64;
+        Ok(PlicCheckpoint {
+            priority: (1..=self.plic_desc.num_sources as u64)
+                .map(|src| self.plic_read(PLIC_PRIORITY + src * 4))
+                .collect::<Result<_, _>>()?,
+            enable: (0..contexts)
+                .map(|c| {
+                    (0..words)
+                        .map(|w| self.plic_read(PLIC_ENABLE + c * PLIC_ENABLE_STRIDE + w * 4))
+                        .collect::<Result<_, _>>()
+                })
+                .collect::<Result<_, _>>()?,
+            threshold: (0..contexts)
+                .map(|c| self.plic_read(PLIC_THRESHOLD + c * PLIC_CONTEXT_STRIDE))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    //the fallible part of a restore: csrs of every hart, clint and plic
+    fn restore_registers(
+        &mut self,
+        csrs: &[Vec<(u32, u64)>],
+        clint: &ClintCheckpoint,
+        plic: &PlicCheckpoint,
+    ) -> Result<(), String> {
+        for (hartid, csrs) in csrs.iter().enumerate() {
+            for (csr, v) in csrs {
+                self.write_csr(hartid, *csr, *v)?;
+            }
+        }
+        self.clint_write(CLINT_MTIME, clint.mtime)?;
+        for (h, v) in clint.mtimecmp.iter().enumerate() {
+            self.clint_write(CLINT_MTIMECMP + h as u64 * 8, *v)?;
+        }
+        for (h, v) in clint.msip.iter().enumerate() {
+            let addr = self.clint_desc.base + CLINT_MSIP + h as u64 * 4;
+            self.sys_bus
+                .write_u32(&addr, v)
+                .map_err(|e| format!("write clint {:#x} fail at {:#x}!", addr, e))?;
+        }
+        for (i, v) in plic.priority.iter().enumerate() {
+            self.plic_write(PLIC_PRIORITY + (i as u64 + 1) * 4, *v)?;
+        }
+        for (c, words) in plic.enable.iter().enumerate() {
+            for (w, v) in words.iter().enumerate() {
+                self.plic_write(
+                    PLIC_ENABLE + c as u64 * PLIC_ENABLE_STRIDE + w as u64 * 4,
+                    *v,
+                )?;
+            }
+        }
+        for (c, v) in plic.threshold.iter().enumerate() {
+            self.plic_write(PLIC_THRESHOLD + c as u64 * PLIC_CONTEXT_STRIDE, *v)?;
+        }
+        Ok(())
+    }
+
+    //fp registers are not reachable through the iss, so harts with f or d can't be saved
+    pub fn save_checkpoint(&self, path: &str) -> Result<(), String> {
+        if let Some(hartid) = self
+            .hart_descs
+            .iter()
+            .position(|d| d.extensions.contains(['f', 'd']))
+        {
+            return Err(format!(
+                "core{} has fp registers, which can't be checkpointed!",
+                hartid
+            ));
+        }
+        let harts = self
+            .processors
+            .iter()
+            .zip(self.hart_states.iter())
+            .enumerate()
+            .map(|(hartid, (p, state))| {
+                let s = p.state();
+                HartCheckpoint {
+                    state: *state as u32,
+                    wfi: s.wfi() || self.wfi_restored[hartid],
+                    privilege: *s.privilege() as u8,
+                    pc: *s.pc(),
+                    xregs: (0..32).map(|i| *s.xreg(i)).collect(),
+                    csrs: self.save_hart_csrs(hartid),
+                }
+            })
+            .collect();
+        Checkpoint {
+            steps: self.steps,
+            mtime_ticks: self.mtime_ticks,
+            hart_steps: self.hart_steps.clone(),
+            harts,
+            clint: self.save_clint()?,
+            plic: self.save_plic()?,
+            mems: self
+                .mems
+                .iter()
+                .map(|(id, mem)| MemCheckpoint {
+                    id: *id,
+                    pages: mem.pages(),
+                })
+                .collect(),
+        }
+        .save(path)
+    }
+
+    //the cluster must have the same topology as the one saving the checkpoint,
+    //the checkpoint is validated first and register writes are undone if one fails,
+    //so a bad checkpoint leaves the cluster unchanged
+    pub fn restore_checkpoint(&mut self, path: &str) -> Result<(), String> {
+        let cp = Checkpoint::load(path)?;
+        let harts_num = self.processors.len();
+        if cp.harts.len() != harts_num
+            || cp.hart_steps.len() != harts_num
+            || cp.clint.msip.len() != harts_num
+            || cp.clint.mtimecmp.len() != harts_num
+            || cp.plic.enable.len() != harts_num
+            || cp.plic.threshold.len() != harts_num
+        {
+            return Err(format!(
+                "{} has {} harts, but cluster has {}!",
+                path,
+                cp.harts.len(),
+                harts_num
+            ));
+        }
+        if cp.plic.priority.len() != self.plic_desc.num_sources as usize
+            || cp
+                .plic
+                .enable
+                .iter()
+                .any(|w| w.len() != self.plic_enable_words())
+        {
+            return Err(format!(
+                "{} has {} plic sources, but cluster has {}!",
+                path,
+                cp.plic.priority.len(),
+                self.plic_desc.num_sources
+            ));
+        }
+        let mems = cp
+            .mems
+            .iter()
+            .map(|m| {
+                self.mems
+                    .iter()
+                    .find(|(id, _)| *id == m.id)
+                    .map(|(_, mem)| (mem.clone(), &m.pages))
+                    .ok_or(format!("{} has unknown sparse memory {:#x}!", path, m.id))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut harts = vec![];
+        for (hartid, h) in cp.harts.iter().enumerate() {
+            let state = match h.state {
+                0 => HartState::Reset,
+                1 => HartState::Running,
+                2 => HartState::Halted,
+                s => return Err(format!("core{} invalid state {} in {}!", hartid, s, path)),
+            };
+            let privilege = match h.privilege {
+                0 => Privilege::U,
+                1 => Privilege::S,
+                3 => Privilege::M,
+                p => {
+                    return Err(format!(
+                        "core{} invalid privilege {} in {}!",
+                        hartid, p, path
+                    ))
+                }
+            };
+            if h.xregs.len() != 32 {
+                return Err(format!(
+                    "core{} has {} gprs in {}!",
+                    hartid,
+                    h.xregs.len(),
+                    path
+                ));
+            }
+            if let Some((csr, _)) = h.csrs.iter().find(|(csr, _)| {
+                !CHECKPOINT_CSRS.contains(csr) || self.read_csr(hartid, *csr).is_err()
+            }) {
+                return Err(format!(
+                    "core{} can't restore csr {:#x} in {}!",
+                    hartid, csr, path
+                ));
+            }
+            harts.push((state, privilege));
+        }
+        let backup = (
+            (0..harts_num)
+                .map(|h| self.save_hart_csrs(h))
+                .collect::<Vec<_>>(),
+            self.save_clint()?,
+            self.save_plic()?,
+        );
+        let csrs = cp.harts.iter().map(|h| h.csrs.clone()).collect::<Vec<_>>();
+        if let Err(e) = self.restore_registers(&csrs, &cp.clint, &cp.plic) {
+            return Err(
+                match self.restore_registers(&backup.0, &backup.1, &backup.2) {
+                    Ok(_) => e,
+                    Err(u) => format!("{} undo fail: {}", e, u),
+                },
+            );
+        }
+        //nothing below fails
+        for (mem, pages) in mems {
+            mem.restore(pages);
+        }
+        for (hartid, (h, (state, privilege))) in cp.harts.iter().zip(harts).enumerate() {
+            self.hart_states[hartid] = state;
+            self.wfi_restored[hartid] = h.wfi;
+            let s = self.processors[hartid].state_mut();
+            for (i, v) in h.xregs.iter().enumerate().skip(1) {
+                s.set_xreg(i as RegT, *v);
+            }
+            s.set_pc(h.pc);
+            s.set_privilege(privilege);
+        }
+        self.steps = cp.steps;
+        self.mtime_ticks = cp.mtime_ticks;
+        self.hart_steps = cp.hart_steps;
+        Ok(())
+    }
+
+    pub fn hart_stats(&self, hartid: usize) -> Result<&HartStats, String> {
+        self.stats
+            .get(hartid)
+            .ok_or(format!("invalid hartid {}!", hartid))
+    }
+
+    pub fn stats(&self) -> ClusterStats<'_> {
+        ClusterStats {
+            steps: self.steps,
+            harts: &self.stats,
+        }
+    }
+
+    pub fn statics(&self) {
+        for (p, desc) in self.processors.iter().zip(self.hart_descs.iter()) {
+            println!(
+                "core{}: {} @ {}Hz, reset_vector {:#x}",
+                p.state().hartid(),
+                desc.isa_string(),
+                desc.freq,
+                desc.reset_vector
+            );
+            println!("{}", p.state().to_string())
+        }
+    }
+
+    pub fn add_ext_window(&mut self, desc: &ExtWindowDesc) -> Result<(), String> {
+        self.check_region_id(desc.id, &format!("ext window {}", desc.name))?;
+        let ext_bus = Box::new(ExtBus {
+            name: desc.name.clone(),
+            id: desc.id,
+            base: desc.base,
+            size: desc.size,
+            mem: desc.backend.build(),
+            fault: self.fault.clone(),
+        });
+        let mem = ext_bus.mem.clone();
+        self.add_sys_region(
+            &desc.name,
+            MemInfo {
+                base: desc.base,
+                size: desc.size,
+            },
+            desc.pma,
+            &Region::io(0, desc.size, ext_bus),
+        )?;
+        if let Some(mem) = mem {
+            self.mems.push((desc.id, mem));
+        }
+        self.ext_windows.push(desc.clone());
+        Ok(())
+    }
+}
+
+const CLINT_MSIP: u64 = 0;
+const CLINT_MTIMECMP: u64 = 0x4000;
+const CLINT_MTIME: u64 = 0xbff8;
+const PLIC_PRIORITY: u64 = 0;
+const PLIC_ENABLE: u64 = 0x2000;
+const PLIC_ENABLE_STRIDE: u64 = 0x80;
+const PLIC_THRESHOLD: u64 = 0x20_0000;
+const PLIC_CONTEXT_STRIDE: u64 = 0x1000;
+const LOCKSTEP_MAX_TRAPS: usize = 16;
+const CSR_SEPC: RegT = 0x141;
+const CSR_SCAUSE: RegT = 0x142;
+const CSR_SATP: RegT = 0x180;
+const CSR_MIE: RegT = 0x304;
+const CSR_MEPC: RegT = 0x341;
+const CSR_MCAUSE: RegT = 0x342;
+const CSR_MIP: RegT = 0x344;
+
+//instruction at `pc` if the last step retired it or it raised an exception, 0 if its fetch
+//faulted, `None` if the step took an interrupt before it
+fn executed_insn(p: &Processor, pc: RegT, trapped: bool, xlen: u32) -> Option<u64> {
+    let state = p.state();
+    if !trapped {
+        return Some(*state.ir() as u64);
+    }
+    let (cause, epc) = match state.privilege() {
+        Privilege::M => (CSR_MCAUSE, CSR_MEPC),
+        _ => (CSR_SCAUSE, CSR_SEPC),
+    };
+    let cause = state.csr(cause).ok()?;
+    if state.csr(epc).ok()? != pc || (cause >> (xlen - 1)) & 1 != 0 {
+        return None;
+    }
+    match cause {
+        //instruction address misaligned, access fault, page fault, ir is not fetched
+        0 | 1 | 12 => Some(0),
+        _ => Some(*state.ir() as u64),
+    }
+}
+
+//clint ticks elapsed in `cycles` cycles at `freq`
+fn clint_ticks(cycles: u64, clint_freq: usize, freq: u64) -> u64 {
+    (cycles as u128 * clint_freq as u128 / freq as u128) as u64
+}
+
+//addresses of the halfwords of the instruction at `pc` if its fetch is not translated,
+//translated fetches are not identified, the hart does not tell its physical address
+fn fetch_addrs(p: &Processor, pc: RegT) -> [Option<u64>; 2] {
+    let state = p.state();
+    let bare = *state.privilege() == Privilege::M
+        || match (state.csr(CSR_SATP), state.config().xlen) {
+            (Ok(satp), XLen::X32) => satp >> 31 == 0,
+            (Ok(satp), XLen::X64) => satp >> 60 == 0,
+            (Err(_), _) => true,
+        };
+    if bare {
+        [Some(pc), Some(pc + 2)]
+    } else {
+        [None; 2]
+    }
+}
+
+//wfi resumes once any enabled interrupt is pending, regardless of global interrupt enable
+fn irq_pending(p: &Processor) -> bool {
+    let state = p.state();
+    match (state.csr(CSR_MIP), state.csr(CSR_MIE)) {
+        (Ok(mip), Ok(mie)) => mip & mie != 0,
+        _ => true,
+    }
+}
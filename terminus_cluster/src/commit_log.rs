@@ -0,0 +1,76 @@
+use crate::retire::{insn_len, Retired};
+use std::fs::File;
+use std::io::{LineWriter, Write};
+
+//spike --log-commits compatible trace of one hart
+pub struct CommitLog {
+    path: String,
+    xlen: u32,
+    file: Option<LineWriter<File>>,
+    enabled: bool,
+}
+
+impl CommitLog {
+    pub fn new(path: String, xlen: u32) -> CommitLog {
+        CommitLog {
+            path,
+            xlen,
+            file: None,
+            enabled: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    //file is created at the first time enabled, following windows are appended
+    pub fn enable(&mut self, en: bool) -> Result<(), String> {
+        if en && self.file.is_none() {
+            self.file =
+                Some(LineWriter::new(File::create(&self.path).map_err(|e| {
+                    format!("create commit log {} fail: {}!", self.path, e)
+                })?));
+        }
+        self.enabled = en;
+        Ok(())
+    }
+
+    pub fn log(&mut self, r: &Retired) -> Result<(), String> {
+        if !self.enabled || r.trap {
+            return Ok(());
+        }
+        let line = format_commit(r, self.xlen);
+        let file = self.file.as_mut().unwrap();
+        writeln!(file, "{}", line)
+            .map_err(|e| format!("write commit log {} fail: {}!", self.path, e))
+    }
+}
+
+pub fn format_commit(r: &Retired, xlen: u32) -> String {
+    let mut line = format!(
+        "core {:>3}: {} {} ({})",
+        r.hartid,
+        r.privilege,
+        hex(r.pc, xlen as usize),
+        hex(r.insn as u64, insn_len(r.insn) * 8)
+    );
+    if let Some((rd, value)) = r.rd {
+        line += &format!(" x{:<2} {}", rd, hex(value, xlen as usize));
+    }
+    for a in r.mem.iter().filter(|a| !a.write) {
+        line += &format!(" mem {}", hex(a.addr, xlen as usize));
+    }
+    for a in r.mem.iter().filter(|a| a.write) {
+        line += &format!(
+            " mem {} {}",
+            hex(a.addr, xlen as usize),
+            hex(a.data, a.size * 8)
+        );
+    }
+    line
+}
+
+fn hex(value: u64, bits: usize) -> String {
+    format!("{:#0width$x}", value, width = bits / 4 + 2)
+}
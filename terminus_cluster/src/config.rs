@@ -0,0 +1,511 @@
+use crate::bus::LocalRegion;
+use crate::mem::SparseMem;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use terminus::global::XLen;
+use terminus::memory::MemInfo;
+use terminus::processor::ProcessorCfg;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MemBackend {
+    //accesses are forwarded to sv by cluster_ext_* dpi calls
+    #[default]
+    Dpi,
+    //accesses are served by rust-owned sparse memory, sv reaches it by cluster_mem_* backdoor
+    Sparse,
+}
+
+impl MemBackend {
+    pub fn build(&self) -> Option<Arc<SparseMem>> {
+        match self {
+            MemBackend::Dpi => None,
+            MemBackend::Sparse => Some(Arc::new(SparseMem::new())),
+        }
+    }
+}
+
+//physical memory attributes, accesses violating them take access faults
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pma {
+    //not modelled by the iss, kept to describe the region
+    pub cacheable: bool,
+    //debugger does not read non-idempotent regions
+    pub idempotent: bool,
+    //amo and lr/sc
+    pub amo: bool,
+    //instruction fetch
+    pub executable: bool,
+    //misaligned data access
+    pub misaligned: bool,
+}
+
+impl Pma {
+    pub fn io() -> Pma {
+        Pma {
+            cacheable: false,
+            idempotent: false,
+            amo: false,
+            executable: false,
+            misaligned: false,
+        }
+    }
+}
+
+//main memory
+impl Default for Pma {
+    fn default() -> Self {
+        Pma {
+            cacheable: true,
+            idempotent: true,
+            amo: true,
+            executable: true,
+            misaligned: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LocalMemDesc {
+    pub name: String,
+    //dpi id of the region, default to (hartid << 4) + index
+    #[serde(default)]
+    pub id: Option<u32>,
+    pub base: u64,
+    pub size: u64,
+    #[serde(default)]
+    pub backend: MemBackend,
+    #[serde(default)]
+    pub pma: Pma,
+}
+
+impl LocalMemDesc {
+    pub fn region(&self, hartid: usize, idx: usize) -> LocalRegion {
+        LocalRegion {
+            name: self.name.clone(),
+            id: self.id.unwrap_or(((hartid as u32) << 4) + idx as u32),
+            info: MemInfo {
+                base: self.base,
+                size: self.size,
+            },
+            mem: self.backend.build(),
+            pma: self.pma,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessorDesc {
+    pub xlen: u32,
+    pub extensions: String,
+    pub freq: usize,
+    pub reset_vector: u64,
+    pub local_mems: Vec<LocalMemDesc>,
+}
+
+impl Default for ProcessorDesc {
+    fn default() -> Self {
+        ProcessorDesc {
+            #[cfg(feature = "rv64")]
+            xlen: 64,
+            #[cfg(not(feature = "rv64"))]
+            xlen: 32,
+            extensions: "mac".to_string(),
+            freq: 1000000000,
+            reset_vector: 0x80100000,
+            local_mems: vec![
+                LocalMemDesc {
+                    name: "ilm".to_string(),
+                    id: None,
+                    base: 0,
+                    size: 4096,
+                    backend: MemBackend::Dpi,
+                    pma: Pma::default(),
+                },
+                LocalMemDesc {
+                    name: "dlm".to_string(),
+                    id: None,
+                    base: 4096,
+                    size: 4096 * 4,
+                    backend: MemBackend::Dpi,
+                    pma: Pma::default(),
+                },
+            ],
+        }
+    }
+}
+
+impl ProcessorDesc {
+    pub fn to_cfg(&self) -> Result<ProcessorCfg, String> {
+        let xlen = match self.xlen {
+            32 => XLen::X32,
+            64 => XLen::X64,
+            x => return Err(format!("invalid xlen {}, only 32 or 64 supported!", x)),
+        };
+        Ok(ProcessorCfg {
+            xlen,
+            enable_dirty: true,
+            extensions: self
+                .extensions
+                .chars()
+                .collect::<Vec<_>>()
+                .into_boxed_slice(),
+            freq: self.freq,
+        })
+    }
+
+    pub fn local_regions(&self, hartid: usize) -> Vec<LocalRegion> {
+        self.local_mems
+            .iter()
+            .enumerate()
+            .map(|(i, m)| m.region(hartid, i))
+            .collect()
+    }
+
+    pub fn isa_string(&self) -> String {
+        format!("rv{}i{}", self.xlen, self.extensions)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExtWindowDesc {
+    pub name: String,
+    pub id: u32,
+    pub base: u64,
+    pub size: u64,
+    #[serde(default)]
+    pub backend: MemBackend,
+    #[serde(default)]
+    pub pma: Pma,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MtimeMode {
+    //mtime advances with cluster steps, a step is one cycle of the fastest hart
+    Free,
+    //mtime only advances by cluster_tick_mtime() or every `steps_per_tick` cluster steps
+    Sim,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClintDesc {
+    pub base: u64,
+    pub size: u64,
+    pub freq: usize,
+    pub mtime: MtimeMode,
+    //0 means mtime is only advanced by cluster_tick_mtime()
+    pub steps_per_tick: u64,
+}
+
+impl ClintDesc {
+    pub fn info(&self) -> MemInfo {
+        MemInfo {
+            base: self.base,
+            size: self.size,
+        }
+    }
+}
+
+impl Default for ClintDesc {
+    fn default() -> Self {
+        ClintDesc {
+            base: 0x02000000,
+            size: 0x000c0000,
+            freq: 100000000,
+            mtime: MtimeMode::Free,
+            steps_per_tick: 0,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlicDesc {
+    pub base: u64,
+    pub size: u64,
+    //number of external interrupt sources, source 0 is reserved
+    pub num_sources: u32,
+}
+
+impl PlicDesc {
+    pub fn info(&self) -> MemInfo {
+        MemInfo {
+            base: self.base,
+            size: self.size,
+        }
+    }
+}
+
+impl Default for PlicDesc {
+    fn default() -> Self {
+        PlicDesc {
+            base: 0x0c000000,
+            size: 0x04000000,
+            num_sources: 32,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevicesDesc {
+    pub clint: ClintDesc,
+    pub plic: PlicDesc,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WatchDesc {
+    //watch an elf symbol, resolved once an elf defining it is loaded by cluster_load_elf
+    #[serde(default)]
+    pub symbol: Option<String>,
+    #[serde(default)]
+    pub base: Option<u64>,
+    //0 means the size of `symbol`
+    #[serde(default)]
+    pub size: u64,
+    //any combination of r, w and x
+    pub kinds: String,
+    //harts to watch, all harts if empty
+    #[serde(default)]
+    pub harts: Vec<usize>,
+    //halt the hart after the hitting instruction
+    #[serde(default)]
+    pub halt: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GdbDesc {
+    //tcp port on 127.0.0.1 of the gdb remote stub, 0 means disabled
+    pub port: u16,
+    //block cluster creation until gdb connects
+    pub wait: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterCfg {
+    pub num_cores: usize,
+    pub processor: ProcessorDesc,
+    pub harts: Vec<ProcessorDesc>,
+    pub ext_windows: Vec<ExtWindowDesc>,
+    pub devices: DevicesDesc,
+    //do not step harts waiting in wfi until an interrupt is pending
+    pub skip_wfi: bool,
+    //commit log of each hart is written to `{commit_log}{hartid}.log`
+    pub commit_log: String,
+    //retirement records buffered per hart for cluster_rvfi_pop
+    pub rvfi_depth: usize,
+    pub gdb: GdbDesc,
+    pub watchpoints: Vec<WatchDesc>,
+    //csv trace of all bus transactions from creation, empty means disabled
+    pub bus_trace: String,
+}
+
+impl Default for ClusterCfg {
+    fn default() -> Self {
+        ClusterCfg {
+            num_cores: 1,
+            processor: ProcessorDesc::default(),
+            harts: vec![],
+            ext_windows: vec![ExtWindowDesc {
+                name: "global".to_string(),
+                id: 0x1000,
+                base: 0x80000000,
+                size: 0x80000000,
+                backend: MemBackend::Dpi,
+                pma: Pma::default(),
+            }],
+            devices: DevicesDesc::default(),
+            skip_wfi: false,
+            commit_log: "commit_core".to_string(),
+            rvfi_depth: 1024,
+            gdb: GdbDesc::default(),
+            watchpoints: vec![],
+            bus_trace: String::new(),
+        }
+    }
+}
+
+//hartmasks are u64
+pub const MAX_HARTS: usize = 64;
+
+impl ClusterCfg {
+    //`harts` describes every hart one by one, otherwise `num_cores` harts share `processor`
+    pub fn hart_descs(&self) -> Vec<ProcessorDesc> {
+        if self.harts.is_empty() {
+            vec![self.processor.clone(); self.num_cores]
+        } else {
+            self.harts.clone()
+        }
+    }
+
+    pub fn check_harts(&self) -> Result<(), String> {
+        let num = self.hart_descs().len();
+        if num > MAX_HARTS {
+            return Err(format!("{} harts, at most {} supported!", num, MAX_HARTS));
+        }
+        for w in &self.watchpoints {
+            if let Some(h) = w.harts.iter().find(|h| **h >= num) {
+                return Err(format!(
+                    "watchpoint on hart {}, but cluster has {} harts!",
+                    h, num
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ClusterCfg, String> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("read {} fail: {}!", path.display(), e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&content)
+                .map_err(|e| format!("parse {} fail: {}!", path.display(), e)),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content)
+                .map_err(|e| format!("parse {} fail: {}!", path.display(), e)),
+            _ => Err(format!(
+                "unknown config format of {}, expect .toml, .yaml or .yml!",
+                path.display()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_str(name: &str, content: &str) -> Result<ClusterCfg, String> {
+        let path =
+            std::env::temp_dir().join(format!("cluster_cfg_{}_{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        let cfg = ClusterCfg::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        cfg
+    }
+
+    #[test]
+    fn yaml() {
+        let cfg = from_str(
+            "yaml.yaml",
+            "
+num_cores: 2
+processor:
+  xlen: 64
+  extensions: mafdc
+devices:
+  clint:
+    mtime: sim
+    steps_per_tick: 10
+ext_windows:
+  - name: ddr
+    id: 1
+    base: 0x80000000
+    size: 0x10000000
+    backend: sparse
+    pma:
+      misaligned: false
+",
+        )
+        .unwrap();
+        assert_eq!(cfg.num_cores, 2);
+        assert_eq!(cfg.hart_descs().len(), 2);
+        assert_eq!(cfg.processor.isa_string(), "rv64imafdc");
+        assert_eq!(cfg.processor.reset_vector, 0x80100000);
+        assert_eq!(cfg.devices.clint.mtime, MtimeMode::Sim);
+        assert_eq!(cfg.devices.clint.steps_per_tick, 10);
+        assert_eq!(cfg.devices.clint.base, 0x02000000);
+        let w = &cfg.ext_windows[0];
+        assert_eq!(w.backend, MemBackend::Sparse);
+        assert!(!w.pma.misaligned);
+        assert!(w.pma.amo);
+    }
+
+    #[test]
+    fn toml() {
+        let cfg = from_str(
+            "toml.toml",
+            "
+skip_wfi = true
+
+[[harts]]
+xlen = 32
+extensions = \"mac\"
+
+[[harts]]
+xlen = 32
+extensions = \"ma\"
+local_mems = [{ name = \"tcm\", base = 0x10000, size = 0x1000, pma = { amo = false } }]
+",
+        )
+        .unwrap();
+        assert!(cfg.skip_wfi);
+        let harts = cfg.hart_descs();
+        assert_eq!(harts.len(), 2);
+        assert_eq!(harts[0].local_mems.len(), 2);
+        let regions = harts[1].local_regions(1);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].id, 0x10);
+        assert!(!regions[0].pma.amo);
+    }
+
+    #[test]
+    fn defaults() {
+        let cfg = from_str("defaults.yml", "{}").unwrap();
+        let default = ClusterCfg::default();
+        assert_eq!(cfg.num_cores, default.num_cores);
+        assert_eq!(cfg.processor.xlen, default.processor.xlen);
+        assert_eq!(cfg.ext_windows.len(), 1);
+        assert_eq!(cfg.ext_windows[0].base, 0x80000000);
+        assert_eq!(cfg.devices.clint.mtime, MtimeMode::Free);
+        assert_eq!(cfg.rvfi_depth, 1024);
+        assert!(!cfg.skip_wfi);
+        assert_eq!(cfg.gdb.port, 0);
+    }
+
+    #[test]
+    fn bad_xlen() {
+        let cfg = from_str("xlen.yaml", "processor:\n  xlen: 128\n").unwrap();
+        assert!(cfg.processor.to_cfg().err().unwrap().contains("xlen 128"));
+    }
+
+    #[test]
+    fn bad_harts() {
+        let cfg = from_str("harts.yaml", "num_cores: 65\n").unwrap();
+        assert!(cfg.check_harts().err().unwrap().contains("65 harts"));
+        let cfg = from_str(
+            "watch.yaml",
+            "num_cores: 2\nwatchpoints:\n  - {base: 0x80000000, size: 4, kinds: w, harts: [64]}\n",
+        )
+        .unwrap();
+        assert!(cfg.check_harts().err().unwrap().contains("hart 64"));
+        let cfg = from_str(
+            "watch.yaml",
+            "num_cores: 2\nwatchpoints:\n  - {base: 0x80000000, size: 4, kinds: w, harts: [1]}\n",
+        )
+        .unwrap();
+        assert!(cfg.check_harts().is_ok());
+    }
+
+    #[test]
+    fn bad_files() {
+        assert!(from_str("unknown.yaml", "num_core: 2\n").is_err());
+        assert!(from_str(
+            "amo.yaml",
+            "processor:\n  local_mems:\n    - {name: ilm, base: 0, size: 4096, amo: false}\n"
+        )
+        .is_err());
+        assert!(from_str("format.json", "{}").is_err());
+        assert!(ClusterCfg::from_file("/nonexistent/cluster_cfg.yaml").is_err());
+    }
+}
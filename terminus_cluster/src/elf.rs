@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use xmas_elf::program::{SegmentData, Type as SegmentType};
+use xmas_elf::sections::SectionData;
+use xmas_elf::symbol_table::{Entry, Type as SymbolType};
+use xmas_elf::ElfFile;
+
+//loadable segment, bytes beyond `data` up to `mem_size` are zero
+pub struct Segment {
+    pub addr: u64,
+    pub data: Vec<u8>,
+    pub mem_size: u64,
+}
+
+pub struct ElfImage {
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+    //name to (address, size)
+    pub symbols: HashMap<String, (u64, u64)>,
+}
+
+impl ElfImage {
+    pub fn load(path: &str) -> Result<ElfImage, String> {
+        let content = std::fs::read(path).map_err(|e| format!("read {} fail: {}!", path, e))?;
+        let elf = ElfFile::new(&content).map_err(|e| format!("parse {} fail: {}!", path, e))?;
+        let mut segments = vec![];
+        for ph in elf.program_iter() {
+            if ph.get_type() != Ok(SegmentType::Load) || ph.mem_size() == 0 {
+                continue;
+            }
+            let data = match ph.get_data(&elf) {
+                Ok(SegmentData::Undefined(data)) => data.to_vec(),
+                _ => return Err(format!("{} has invalid load segment {}!", path, ph)),
+            };
+            segments.push(Segment {
+                addr: ph.physical_addr(),
+                data,
+                mem_size: ph.mem_size(),
+            })
+        }
+        let mut symbols = HashMap::new();
+        for section in elf.section_iter() {
+            macro_rules! add_symbols {
+                ($entries:expr) => {
+                    for e in $entries.iter().filter(|e| {
+                        e.shndx() != 0
+                            && matches!(
+                                e.get_type(),
+                                Ok(SymbolType::NoType | SymbolType::Object | SymbolType::Func)
+                            )
+                    }) {
+                        if let Ok(name) = e.get_name(&elf) {
+                            if !name.is_empty() {
+                                symbols.insert(name.to_string(), (e.value(), e.size()));
+                            }
+                        }
+                    }
+                };
+            }
+            match section.get_data(&elf) {
+                Ok(SectionData::SymbolTable32(entries)) => add_symbols!(entries),
+                Ok(SectionData::SymbolTable64(entries)) => add_symbols!(entries),
+                _ => {}
+            }
+        }
+        Ok(ElfImage {
+            entry: elf.header.pt2.entry_point(),
+            segments,
+            symbols,
+        })
+    }
+}
@@ -0,0 +1,681 @@
+use crate::cluster::{Cluster, HartState};
+use std::collections::HashSet;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+//socket is polled once every POLL_INTERVAL hart steps while the cluster is running
+const POLL_INTERVAL: u64 = 4096;
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+//max packet size announced to gdb, a memory read reply takes 2 chars per byte
+const PACKET_SIZE: usize = 0x4000;
+const MAX_READ: u64 = (PACKET_SIZE / 2 - 4) as u64;
+//gdb numbers csrs from 65
+const GDB_PC: usize = 32;
+const GDB_CSR_BASE: usize = 65;
+const GPR_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+//csrs described to gdb, those the hart can't read are left out
+#[rustfmt::skip]
+const GDB_CSRS: [(&str, u32); 36] = [
+    ("fflags", 0x001), ("frm", 0x002), ("fcsr", 0x003),
+    ("sstatus", 0x100), ("sie", 0x104), ("stvec", 0x105), ("scounteren", 0x106),
+    ("sscratch", 0x140), ("sepc", 0x141), ("scause", 0x142), ("stval", 0x143), ("sip", 0x144),
+    ("satp", 0x180),
+    ("mstatus", 0x300), ("misa", 0x301), ("medeleg", 0x302), ("mideleg", 0x303),
+    ("mie", 0x304), ("mtvec", 0x305), ("mcounteren", 0x306),
+    ("mscratch", 0x340), ("mepc", 0x341), ("mcause", 0x342), ("mtval", 0x343), ("mip", 0x344),
+    ("pmpcfg0", 0x3a0), ("pmpaddr0", 0x3b0),
+    ("mcycle", 0xb00), ("minstret", 0xb02),
+    ("cycle", 0xc00), ("time", 0xc01), ("instret", 0xc02),
+    ("mvendorid", 0xf11), ("marchid", 0xf12), ("mimpid", 0xf13), ("mhartid", 0xf14),
+];
+
+struct GdbConn {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl GdbConn {
+    fn new(stream: TcpStream) -> Result<GdbConn, String> {
+        stream
+            .set_nodelay(true)
+            .map_err(|e| format!("gdb: set nodelay fail: {}!", e))?;
+        let writer = stream
+            .try_clone()
+            .map_err(|e| format!("gdb: clone stream fail: {}!", e))?;
+        Ok(GdbConn {
+            reader: BufReader::new(stream),
+            writer,
+        })
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut b = [0u8];
+        match self.reader.read(&mut b) {
+            Ok(1) => Some(b[0]),
+            _ => None,
+        }
+    }
+
+    //return None if connection is closed
+    fn read_packet(&mut self) -> Option<String> {
+        loop {
+            //acks, nacks and interrupts out of packets are ignored
+            while self.read_byte()? != b'$' {}
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    b => data.push(b),
+                }
+            }
+            let cs = [self.read_byte()?, self.read_byte()?];
+            let expected = checksum(&data);
+            if std::str::from_utf8(&cs)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                == Some(expected)
+            {
+                self.writer.write_all(b"+").ok()?;
+                return Some(String::from_utf8_lossy(&data).to_string());
+            }
+            self.writer.write_all(b"-").ok()?;
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> Option<()> {
+        let cs = checksum(data.as_bytes());
+        self.writer
+            .write_all(format!("${}#{:02x}", data, cs).as_bytes())
+            .ok()
+    }
+
+    //return Some(true) if gdb sends an interrupt, None if connection is closed
+    fn poll_interrupt(&mut self) -> Option<bool> {
+        self.reader.get_ref().set_nonblocking(true).ok()?;
+        let mut b = [0u8];
+        let result = match self.reader.read(&mut b) {
+            Ok(0) => None,
+            Ok(_) => Some(b[0] == 0x03),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Some(false),
+            Err(_) => None,
+        };
+        self.reader.get_ref().set_nonblocking(false).ok()?;
+        result
+    }
+}
+
+enum Action {
+    Reply(String),
+    Continue,
+    Detach(Option<String>),
+}
+
+//all-stop gdb remote stub, every hart is a thread with thread id hartid + 1
+pub struct GdbServer {
+    listener: TcpListener,
+    conn: Option<GdbConn>,
+    breakpoints: HashSet<u64>,
+    //cluster is paused while the debugger holds it
+    stopped: bool,
+    stop_signal: u8,
+    stop_thread: usize,
+    g_thread: usize,
+    c_thread: Option<usize>,
+    //pc of each hart when resumed, the breakpoint under it is stepped over
+    resume_pcs: Vec<Option<u64>>,
+    polls: u64,
+}
+
+impl GdbServer {
+    //listen on 127.0.0.1:`port`, block until gdb connects if `wait`
+    pub fn listen(port: u16, wait: bool) -> Result<GdbServer, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| format!("gdb: bind port {} fail: {}!", port, e))?;
+        eprintln!("gdb: listening on 127.0.0.1:{}", port);
+        let mut server = GdbServer {
+            listener,
+            conn: None,
+            breakpoints: HashSet::new(),
+            stopped: false,
+            stop_signal: SIGTRAP,
+            stop_thread: 0,
+            g_thread: 0,
+            c_thread: None,
+            resume_pcs: vec![],
+            polls: 0,
+        };
+        if wait {
+            let (stream, _) = server
+                .listener
+                .accept()
+                .map_err(|e| format!("gdb: accept fail: {}!", e))?;
+            server.attach(stream)?;
+        }
+        server
+            .listener
+            .set_nonblocking(true)
+            .map_err(|e| format!("gdb: set nonblocking fail: {}!", e))?;
+        Ok(server)
+    }
+
+    fn attach(&mut self, stream: TcpStream) -> Result<(), String> {
+        if let Ok(addr) = stream.peer_addr() {
+            eprintln!("gdb: connected from {}", addr);
+        }
+        stream
+            .set_nonblocking(false)
+            .map_err(|e| format!("gdb: set blocking fail: {}!", e))?;
+        self.conn = Some(GdbConn::new(stream)?);
+        self.stop(0, SIGTRAP);
+        Ok(())
+    }
+
+    fn detach(&mut self) {
+        eprintln!("gdb: detached");
+        self.conn = None;
+        self.stopped = false;
+        self.breakpoints.clear();
+        self.resume_pcs.clear();
+    }
+
+    fn stop(&mut self, hartid: usize, signal: u8) {
+        self.stopped = true;
+        self.stop_signal = signal;
+        self.stop_thread = hartid;
+        self.g_thread = hartid;
+    }
+
+    fn stop_reply(&self) -> String {
+        format!(
+            "T{:02x}thread:{:x};",
+            self.stop_signal,
+            self.stop_thread + 1
+        )
+    }
+
+    //called before hart `hartid` steps, block while the debugger holds the cluster
+    pub fn poll(&mut self, cluster: &mut Cluster, hartid: usize) {
+        self.polls += 1;
+        if self.polls.is_multiple_of(POLL_INTERVAL) {
+            match &mut self.conn {
+                None => {
+                    if let Ok((stream, _)) = self.listener.accept() {
+                        self.attach(stream).unwrap_or_else(|e| eprintln!("{}", e));
+                    }
+                }
+                Some(conn) => match conn.poll_interrupt() {
+                    Some(true) => {
+                        self.stop(hartid, SIGINT);
+                        self.notify_stop();
+                    }
+                    Some(false) => {}
+                    None => self.detach(),
+                },
+            }
+        }
+        if !self.stopped && self.hit_breakpoint(cluster, hartid) {
+            self.stop(hartid, SIGTRAP);
+            self.notify_stop();
+        }
+        while self.stopped {
+            let pkt = match self.conn.as_mut().and_then(|c| c.read_packet()) {
+                Some(pkt) => pkt,
+                None => {
+                    self.detach();
+                    break;
+                }
+            };
+            let reply = match self.handle(cluster, &pkt) {
+                Action::Reply(r) => Some(r),
+                Action::Continue => {
+                    self.resume_pcs = (0..cluster.processors.len())
+                        .map(|h| cluster.read_pc(h).ok())
+                        .collect();
+                    self.stopped = false;
+                    None
+                }
+                Action::Detach(r) => {
+                    if let (Some(r), Some(conn)) = (r, self.conn.as_mut()) {
+                        conn.write_packet(&r);
+                    }
+                    self.detach();
+                    None
+                }
+            };
+            if let (Some(r), Some(conn)) = (reply, self.conn.as_mut()) {
+                if conn.write_packet(&r).is_none() {
+                    self.detach();
+                }
+            }
+        }
+    }
+
+    fn notify_stop(&mut self) {
+        let reply = self.stop_reply();
+        if let Some(conn) = self.conn.as_mut() {
+            if conn.write_packet(&reply).is_none() {
+                self.detach();
+            }
+        }
+    }
+
+    fn hit_breakpoint(&mut self, cluster: &Cluster, hartid: usize) -> bool {
+        if self.breakpoints.is_empty() || cluster.hart_state(hartid) != Ok(HartState::Running) {
+            return false;
+        }
+        let pc = cluster.processors[hartid].state().pc();
+        if let Some(resume_pc) = self.resume_pcs.get_mut(hartid) {
+            if *resume_pc == Some(*pc) {
+                return false;
+            }
+            *resume_pc = None;
+        }
+        self.breakpoints.contains(pc)
+    }
+
+    fn handle(&mut self, cluster: &mut Cluster, pkt: &str) -> Action {
+        let (cmd, args) = pkt.split_at(pkt.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match cmd {
+            "?" => self.stop_reply(),
+            "q" => self.query(cluster, args),
+            "H" => self.set_thread(cluster, args),
+            "T" => match parse_thread(args) {
+                Some(Some(h)) if h < cluster.processors.len() => "OK".to_string(),
+                _ => "E01".to_string(),
+            },
+            "g" => self.read_regs(cluster),
+            "G" => self.write_regs(cluster, args),
+            "p" => self.read_reg(cluster, args),
+            "P" => self.write_reg(cluster, args),
+            "m" => self.read_mem(cluster, args),
+            "M" => self.write_mem(cluster, args),
+            "Z" | "z" => self.breakpoint(cmd == "Z", args),
+            "c" | "C" => return Action::Continue,
+            "s" | "S" => self.step(cluster, self.c_thread.unwrap_or(self.g_thread)),
+            "v" => return self.v_packet(cluster, args),
+            "D" => return Action::Detach(Some("OK".to_string())),
+            "k" => return Action::Detach(None),
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    fn query(&self, cluster: &Cluster, args: &str) -> String {
+        if args.starts_with("Supported") {
+            format!(
+                "PacketSize={:x};qXfer:features:read+;vContSupported+",
+                PACKET_SIZE
+            )
+        } else if args == "C" {
+            format!("QC{:x}", self.g_thread + 1)
+        } else if args == "fThreadInfo" {
+            let threads = (1..=cluster.processors.len())
+                .map(|t| format!("{:x}", t))
+                .collect::<Vec<_>>();
+            format!("m{}", threads.join(","))
+        } else if args == "sThreadInfo" {
+            "l".to_string()
+        } else if args == "Attached" {
+            "1".to_string()
+        } else if let Some(req) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let csrs = GDB_CSRS
+                .iter()
+                .filter(|(_, csr)| cluster.read_csr(self.g_thread, *csr).is_ok())
+                .copied()
+                .collect::<Vec<_>>();
+            let xml = target_xml(cluster.hart_descs[self.g_thread].xlen, &csrs);
+            match parse_pair(req, ',') {
+                Some((offset, len)) => {
+                    let start = std::cmp::min(offset as usize, xml.len());
+                    let end = std::cmp::min(start + len as usize, xml.len());
+                    let prefix = if end == xml.len() { "l" } else { "m" };
+                    format!("{}{}", prefix, &xml[start..end])
+                }
+                None => "E01".to_string(),
+            }
+        } else {
+            String::new()
+        }
+    }
+
+    fn set_thread(&mut self, cluster: &Cluster, args: &str) -> String {
+        let (op, tid) = args.split_at(std::cmp::min(1, args.len()));
+        let thread = match parse_thread(tid) {
+            Some(Some(h)) if h >= cluster.processors.len() => return "E01".to_string(),
+            Some(t) => t,
+            None => return "E01".to_string(),
+        };
+        match op {
+            "g" => self.g_thread = thread.unwrap_or(self.g_thread),
+            "c" => self.c_thread = thread,
+            _ => return "E01".to_string(),
+        }
+        "OK".to_string()
+    }
+
+    fn step(&mut self, cluster: &mut Cluster, hartid: usize) -> String {
+        if let Err(e) = cluster.debug_step(hartid) {
+            eprintln!("gdb: {}", e);
+        }
+        self.stop(hartid, SIGTRAP);
+        self.stop_reply()
+    }
+
+    fn v_packet(&mut self, cluster: &mut Cluster, args: &str) -> Action {
+        if args == "Cont?" {
+            return Action::Reply("vCont;c;C;s;S".to_string());
+        }
+        let actions = match args.strip_prefix("Cont;") {
+            Some(actions) => actions,
+            None => return Action::Reply(String::new()),
+        };
+        //only one hart is stepped, others stay stopped
+        for action in actions.split(';') {
+            let (op, thread) = match action.split_once(':') {
+                Some((op, tid)) => (op, parse_thread(tid).flatten()),
+                None => (action, None),
+            };
+            if op.starts_with('s') || op.starts_with('S') {
+                let hartid = thread.unwrap_or(self.c_thread.unwrap_or(self.g_thread));
+                if hartid >= cluster.processors.len() {
+                    return Action::Reply("E01".to_string());
+                }
+                return Action::Reply(self.step(cluster, hartid));
+            }
+        }
+        Action::Continue
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (kind, addr) = match (fields.next(), fields.next().map(parse_hex)) {
+            (Some(kind), Some(Some(addr))) => (kind, addr),
+            _ => return "E01".to_string(),
+        };
+        //software and hardware breakpoints are the same to the iss
+        if kind != "0" && kind != "1" {
+            return String::new();
+        }
+        if insert {
+            self.breakpoints.insert(addr);
+        } else {
+            self.breakpoints.remove(&addr);
+        }
+        "OK".to_string()
+    }
+
+    fn reg_bytes(&self, cluster: &Cluster) -> usize {
+        cluster.hart_descs[self.g_thread].xlen as usize / 8
+    }
+
+    fn read_reg_value(&self, cluster: &Cluster, idx: usize) -> Result<u64, String> {
+        match idx {
+            0..=31 => cluster.read_gpr(self.g_thread, idx as u32),
+            GDB_PC => cluster.read_pc(self.g_thread),
+            _ => match idx.checked_sub(GDB_CSR_BASE) {
+                Some(csr) if csr < 4096 => cluster.read_csr(self.g_thread, csr as u32),
+                _ => Err(format!("invalid register {}!", idx)),
+            },
+        }
+    }
+
+    fn write_reg_value(&self, cluster: &mut Cluster, idx: usize, value: u64) -> Result<(), String> {
+        match idx {
+            0..=31 => cluster.write_gpr(self.g_thread, idx as u32, value),
+            GDB_PC => cluster.write_pc(self.g_thread, value),
+            _ => match idx.checked_sub(GDB_CSR_BASE) {
+                Some(csr) if csr < 4096 => cluster.write_csr(self.g_thread, csr as u32, value),
+                _ => Err(format!("invalid register {}!", idx)),
+            },
+        }
+    }
+
+    fn read_regs(&self, cluster: &Cluster) -> String {
+        let bytes = self.reg_bytes(cluster);
+        (0..=GDB_PC)
+            .map(|i| encode_le(self.read_reg_value(cluster, i).unwrap_or(0), bytes))
+            .collect()
+    }
+
+    fn write_regs(&self, cluster: &mut Cluster, args: &str) -> String {
+        let chars = self.reg_bytes(cluster) * 2;
+        for i in 0..=GDB_PC {
+            let value = match args.get(i * chars..(i + 1) * chars).and_then(decode_le) {
+                Some(v) => v,
+                None => return "E01".to_string(),
+            };
+            if self.write_reg_value(cluster, i, value).is_err() {
+                return "E01".to_string();
+            }
+        }
+        "OK".to_string()
+    }
+
+    fn read_reg(&self, cluster: &Cluster, args: &str) -> String {
+        let bytes = self.reg_bytes(cluster);
+        match parse_hex(args).map(|i| self.read_reg_value(cluster, i as usize)) {
+            Some(Ok(v)) => encode_le(v, bytes),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_reg(&self, cluster: &mut Cluster, args: &str) -> String {
+        let (idx, value) = match args.split_once('=') {
+            Some((idx, value)) => (parse_hex(idx), decode_le(value)),
+            None => return "E01".to_string(),
+        };
+        match (idx, value) {
+            (Some(idx), Some(value))
+                if self.write_reg_value(cluster, idx as usize, value).is_ok() =>
+            {
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    //debugger accesses do not hit watchpoints
+    fn read_mem(&self, cluster: &Cluster, args: &str) -> String {
+        let (addr, len) = match parse_mem_range(args, MAX_READ) {
+            Some(p) => p,
+            None => return "E01".to_string(),
+        };
+        let bus = &cluster.core_buses[self.g_thread];
+        let mut bytes = vec![0u8; len as usize];
+        if bus.read_bytes(&addr, &mut bytes).is_ok() {
+            return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        }
+        //find where the read fails byte by byte
+        let mut data = String::new();
+        for a in addr..addr + len {
+            let mut b = 0u8;
+            if bus.read_bytes(&a, std::slice::from_mut(&mut b)).is_err() {
+                //partial read is allowed
+                return if data.is_empty() {
+                    "E01".to_string()
+                } else {
+                    data
+                };
+            }
+            data.push_str(&format!("{:02x}", b));
+        }
+        data
+    }
+
+    fn write_mem(&self, cluster: &Cluster, args: &str) -> String {
+        let (head, data) = match args.split_once(':') {
+            Some(p) => p,
+            None => return "E01".to_string(),
+        };
+        let (addr, len) = match parse_mem_range(head, u64::MAX) {
+            Some(p) => p,
+            None => return "E01".to_string(),
+        };
+        let bus = &cluster.core_buses[self.g_thread];
+        for i in 0..len {
+            let b = match data
+                .get(i as usize * 2..i as usize * 2 + 2)
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+            {
+                Some(b) => b,
+                None => return "E01".to_string(),
+            };
+            if bus.write_bytes(&(addr + i), &[b]).is_err() {
+                return "E01".to_string();
+            }
+        }
+        "OK".to_string()
+    }
+}
+
+//modulo 256 sum of packet data
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |s, b| s.wrapping_add(*b))
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+fn parse_pair(s: &str, sep: char) -> Option<(u64, u64)> {
+    let (a, b) = s.split_once(sep)?;
+    Some((parse_hex(a)?, parse_hex(b)?))
+}
+
+//(addr, len) of a memory packet with len clamped to `max`, None if the range wraps around
+fn parse_mem_range(s: &str, max: u64) -> Option<(u64, u64)> {
+    let (addr, len) = parse_pair(s, ',')?;
+    let len = len.min(max);
+    addr.checked_add(len)?;
+    Some((addr, len))
+}
+
+//thread id to hartid, Some(None) for any or all threads
+fn parse_thread(s: &str) -> Option<Option<usize>> {
+    match s {
+        "0" | "-1" => Some(None),
+        _ => parse_hex(s)
+            .and_then(|t| (t as usize).checked_sub(1))
+            .map(Some),
+    }
+}
+
+fn encode_le(value: u64, bytes: usize) -> String {
+    value.to_le_bytes()[..bytes]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn decode_le(s: &str) -> Option<u64> {
+    if !s.len().is_multiple_of(2) || s.len() > 16 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    for (i, b) in bytes.iter_mut().take(s.len() / 2).enumerate() {
+        *b = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(u64::from_le_bytes(bytes))
+}
+
+fn target_xml(xlen: u32, csrs: &[(&str, u32)]) -> String {
+    let mut regs = GPR_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            format!(
+                "<reg name=\"{}\" bitsize=\"{}\" type=\"int\" regnum=\"{}\"/>",
+                name, xlen, i
+            )
+        })
+        .collect::<String>();
+    regs.push_str(&format!(
+        "<reg name=\"pc\" bitsize=\"{}\" type=\"code_ptr\" regnum=\"{}\"/>",
+        xlen, GDB_PC
+    ));
+    let csrs = csrs
+        .iter()
+        .map(|(name, csr)| {
+            format!(
+                "<reg name=\"{}\" bitsize=\"{}\" type=\"int\" regnum=\"{}\"/>",
+                name,
+                xlen,
+                GDB_CSR_BASE + *csr as usize
+            )
+        })
+        .collect::<String>();
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\"><architecture>riscv:rv{}</architecture><feature name=\"org.gnu.gdb.riscv.cpu\">{}</feature><feature name=\"org.gnu.gdb.riscv.csr\">{}</feature></target>",
+        xlen, regs, csrs
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packet_checksum() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(b"qSupported"), 0x37);
+        //wraps around
+        assert_eq!(checksum(&[0xff, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn thread_ids() {
+        assert_eq!(parse_thread("0"), Some(None));
+        assert_eq!(parse_thread("-1"), Some(None));
+        assert_eq!(parse_thread("1"), Some(Some(0)));
+        assert_eq!(parse_thread("a"), Some(Some(9)));
+        assert_eq!(parse_thread("x"), None);
+        assert_eq!(parse_thread(""), None);
+    }
+
+    #[test]
+    fn le_values() {
+        assert_eq!(decode_le("78563412"), Some(0x1234_5678));
+        assert_eq!(decode_le("efbeadde00000080"), Some(0x8000_0000_dead_beef));
+        assert_eq!(decode_le(""), Some(0));
+        assert_eq!(decode_le("123"), None);
+        assert_eq!(decode_le("xx"), None);
+        assert_eq!(decode_le("000000000000000000"), None);
+        assert_eq!(encode_le(0x1234_5678, 4), "78563412");
+        assert_eq!(
+            decode_le(&encode_le(0x8000_0000_dead_beef, 8)),
+            Some(0x8000_0000_dead_beef)
+        );
+    }
+
+    #[test]
+    fn mem_ranges() {
+        assert_eq!(
+            parse_mem_range("80000000,4", MAX_READ),
+            Some((0x8000_0000, 4))
+        );
+        assert_eq!(
+            parse_mem_range("80000000,100000", MAX_READ),
+            Some((0x8000_0000, MAX_READ))
+        );
+        assert_eq!(parse_mem_range("ffffffffffffffff,8", MAX_READ), None);
+        assert_eq!(
+            parse_mem_range("fffffffffffffff0,8", u64::MAX),
+            Some((u64::MAX - 15, 8))
+        );
+        assert_eq!(parse_mem_range("fffffffffffffff8,8", u64::MAX), None);
+        assert_eq!(parse_mem_range("80000000", MAX_READ), None);
+    }
+
+    #[test]
+    fn pairs() {
+        assert_eq!(parse_pair("80000000,4", ','), Some((0x8000_0000, 4)));
+        assert_eq!(parse_pair("80000000:4", ','), None);
+        assert_eq!(parse_pair("80000000,", ','), None);
+    }
+}
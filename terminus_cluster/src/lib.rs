@@ -1,138 +1,749 @@
 #![allow(dead_code)]
 extern crate paste;
 extern crate terminus;
+use std::cell::{RefCell, RefMut};
+use std::ffi::CStr;
+use std::os::raw::c_char;
 use std::rc::Rc;
-use terminus::devices::bus::TerminusBus;
-use terminus::devices::clint::*;
-use terminus::global::*;
-use terminus::memory::{region::*, MemInfo};
-use terminus::processor::Processor;
-use terminus::processor::ProcessorCfg;
+use std::sync::{Arc, Mutex};
 
 mod bus;
-use bus::{CoreBus, ExtBus};
-
-struct Cluster {
-    processors: Vec<Processor>,
-    sys_bus: Option<Rc<TerminusBus>>,
-}
-
-static mut CLUSTER: Cluster = Cluster {
-    processors: vec![],
-    sys_bus: None,
-};
-
-#[allow(static_mut_refs)]
-#[no_mangle]
-extern "C" fn cluster_init(num_cores: u32) {
-    let configs = vec![
-        ProcessorCfg {
-            #[cfg(feature = "rv64")]
-            xlen: XLen::X64,
-            #[cfg(not(feature = "rv64"))]
-            xlen: XLen::X32,
-            enable_dirty: true,
-            extensions: vec!['m', 'a', 'c'].into_boxed_slice(),
-            freq: 1000000000,
-        };
-        num_cores as usize
-    ];
-
-    let sys_bus = Rc::new(TerminusBus::new());
-    let clint = Rc::new(Timer::new(100000000));
-    let ext_bus = Box::new(ExtBus {
-        name: "global".to_string(),
-        id: 0x1000,
-        base: 0x80000000,
-        size: 0x80000000,
+mod checkpoint;
+mod cluster;
+mod commit_log;
+mod config;
+mod elf;
+mod gdb;
+mod lockstep;
+mod mem;
+mod retire;
+mod rvfi;
+mod stats;
+mod trace;
+mod watch;
+use cluster::Cluster;
+use config::{ClusterCfg, ExtWindowDesc, MemBackend, Pma};
+use lockstep::RtlRetire;
+use mem::SparseMem;
+
+//a dpi call from sv while the cluster is calling out to sv must not alias it
+struct ClusterSlot {
+    handle: u32,
+    cluster: RefCell<Cluster>,
+}
+
+impl ClusterSlot {
+    fn get(&self) -> RefMut<'_, Cluster> {
+        self.cluster.try_borrow_mut().unwrap_or_else(|_| {
+            panic!(
+                "cluster {} is re-entered by a dpi call while it is busy!",
+                self.handle
+            )
+        })
+    }
+}
+
+//harts of terminus are not thread-safe, so a cluster is only reachable from the thread that
+//created it, normally the simulator thread
+thread_local! {
+    static CLUSTERS: RefCell<Vec<Option<Rc<ClusterSlot>>>> = const { RefCell::new(vec![]) };
+}
+
+//sparse memories of a cluster as (dpi id, memory)
+type Backdoor = Vec<(u32, Arc<SparseMem>)>;
+
+//backdoors of every cluster by handle, shared by all threads, so backdoor dpi calls also work
+//from threads of the testbench, e.g. mailbox servers of tb_dpi
+static BACKDOORS: Mutex<Vec<Backdoor>> = Mutex::new(vec![]);
+
+fn cluster(handle: u32) -> Rc<ClusterSlot> {
+    CLUSTERS
+        .with(|c| c.borrow().get(handle as usize).cloned().flatten())
+        .unwrap_or_else(|| {
+            if handle < cluster_count() {
+                panic!(
+                    "cluster {} is called from another thread than the one that created it!",
+                    handle
+                )
+            }
+            panic!("invalid cluster handle {}!", handle)
+        })
+}
+
+//handles created by the current thread
+fn local_handles() -> Vec<u32> {
+    CLUSTERS.with(|c| {
+        c.borrow()
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_some())
+            .map(|(handle, _)| handle as u32)
+            .collect()
+    })
+}
+
+//publish sparse memories of cluster `handle` to backdoor dpi calls
+fn publish_mems(handle: u32) {
+    let mems = cluster(handle).get().mems().to_vec();
+    BACKDOORS.lock().unwrap()[handle as usize] = mems;
+}
+
+fn add_cluster(cfg: &ClusterCfg) -> u32 {
+    let c = Cluster::new(cfg).unwrap_or_else(|e| panic!("cluster_create fail: {}", e));
+    let handle = {
+        let mut backdoors = BACKDOORS.lock().unwrap();
+        backdoors.push(vec![]);
+        backdoors.len() as u32 - 1
+    };
+    CLUSTERS.with(|cs| {
+        let mut cs = cs.borrow_mut();
+        cs.resize(handle as usize + 1, None);
+        cs[handle as usize] = Some(Rc::new(ClusterSlot {
+            handle,
+            cluster: RefCell::new(c),
+        }))
     });
-    sys_bus
-        .space_mut()
-        .add_region(
-            "ext_bus",
-            &Region::remap(ext_bus.base, &Region::io(0, ext_bus.size, ext_bus)),
-        )
-        .unwrap();
-    sys_bus
-        .space_mut()
-        .add_region(
-            "clint",
-            &Region::remap(
-                0x02000000,
-                &Region::io(0, 0x000c0000, Box::new(Clint::new(&clint))),
-            ),
-        )
-        .unwrap();
-    for cfg in configs {
-        let core_bus = Rc::new(CoreBus::new(
-            &sys_bus,
-            format!("core{}", unsafe { CLUSTER.processors.len() }),
-            unsafe { CLUSTER.processors.len() } as u32,
-            MemInfo {
-                base: 0,
-                size: 4096,
-            },
-            MemInfo {
-                base: 4096,
-                size: 4096 * 4,
-            },
-        ));
-        let p = Processor::new(
-            unsafe { CLUSTER.processors.len() },
-            cfg,
-            &core_bus,
-            Some(clint.alloc_irq()),
-            None,
-        );
-        unsafe { CLUSTER.processors.push(p) }
-    }
-    unsafe {
-        CLUSTER.sys_bus = Some(sys_bus);
+    publish_mems(handle);
+    handle
+}
+
+//create a cluster with default topology of `num_cores` harts, return its handle
+#[no_mangle]
+extern "C" fn cluster_create_default(num_cores: u32) -> u32 {
+    add_cluster(&ClusterCfg {
+        num_cores: num_cores as usize,
+        ..Default::default()
+    })
+}
+
+//same as cluster_create_default, but ext windows are backed by rust-owned sparse memory
+#[no_mangle]
+extern "C" fn cluster_create_default_sparse(num_cores: u32) -> u32 {
+    let mut cfg = ClusterCfg {
+        num_cores: num_cores as usize,
+        ..Default::default()
     };
+    for w in &mut cfg.ext_windows {
+        w.backend = MemBackend::Sparse;
+    }
+    add_cluster(&cfg)
 }
 
+//create a cluster described by config file `path`, return its handle
 #[no_mangle]
-extern "C" fn cluster_reset_core(hartid: u32, boot_addr: u64) {
-    unsafe {
-        CLUSTER.processors[hartid as usize]
-            .reset(boot_addr)
-            .expect(format!("reset core {} to {:#x} fail!", hartid, boot_addr).as_str());
+extern "C" fn cluster_create(path: *const c_char) -> u32 {
+    let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap();
+    add_cluster(&ClusterCfg::from_file(path).unwrap_or_else(|e| panic!("{}", e)))
+}
+
+#[no_mangle]
+extern "C" fn cluster_count() -> u32 {
+    BACKDOORS.lock().unwrap().len() as u32
+}
+
+#[no_mangle]
+extern "C" fn cluster_add_ext_window(
+    handle: u32,
+    name: *const c_char,
+    id: u32,
+    base: u64,
+    size: u64,
+) -> u32 {
+    let name = unsafe { CStr::from_ptr(name) }.to_str().unwrap();
+    let desc = ExtWindowDesc {
+        name: name.to_string(),
+        id,
+        base,
+        size,
+        backend: MemBackend::Dpi,
+        pma: Pma::default(),
+    };
+    let result = cluster(handle).get().add_ext_window(&desc);
+    match result {
+        Ok(_) => {
+            publish_mems(handle);
+            0
+        }
+        Err(e) => {
+            eprintln!("cluster_add_ext_window: {}", e);
+            1
+        }
+    }
+}
+
+//return the dpi id of local region `name` of `hartid`, 0xffffffff if not exists
+#[no_mangle]
+extern "C" fn cluster_local_region_id(handle: u32, hartid: u32, name: *const c_char) -> u32 {
+    let name = unsafe { CStr::from_ptr(name) }.to_str().unwrap();
+    cluster(handle)
+        .get()
+        .core_buses
+        .get(hartid as usize)
+        .and_then(|b| b.local_region(name))
+        .map_or(0xffffffff, |r| r.id)
+}
+
+//return 0 and fill base and size of local region `name` of `hartid`, 1 if not exists
+#[no_mangle]
+extern "C" fn cluster_local_region_info(
+    handle: u32,
+    hartid: u32,
+    name: *const c_char,
+    base: &mut u64,
+    size: &mut u64,
+) -> u32 {
+    let name = unsafe { CStr::from_ptr(name) }.to_str().unwrap();
+    match cluster(handle)
+        .get()
+        .core_buses
+        .get(hartid as usize)
+        .and_then(|b| b.local_region(name))
+    {
+        Some(r) => {
+            *base = r.info.base;
+            *size = r.info.size;
+            0
+        }
+        None => 1,
+    }
+}
+
+//drive external interrupt source `src` of plic, return 1 if `src` is invalid
+#[no_mangle]
+extern "C" fn cluster_set_irq_line(handle: u32, src: u32, level: u32) -> u32 {
+    match cluster(handle).get().set_irq_line(src, level != 0) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("cluster_set_irq_line: {}", e);
+            1
+        }
     }
+}
+
+//sparse memory `id` of cluster `handle`, it is not borrowed from the cluster, so backdoor calls
+//are valid from any thread and while the cluster is busy
+fn sparse_mem(handle: u32, id: u32) -> Arc<SparseMem> {
+    BACKDOORS
+        .lock()
+        .unwrap()
+        .get(handle as usize)
+        .unwrap_or_else(|| panic!("invalid cluster handle {}!", handle))
+        .iter()
+        .find(|(i, _)| *i == id)
+        .map(|(_, m)| m.clone())
+        .unwrap_or_else(|| panic!("no sparse memory with id {:#x}!", id))
+}
+
+#[no_mangle]
+extern "C" fn cluster_mem_write_bd(handle: u32, id: u32, addr: u64, data: u8) {
+    sparse_mem(handle, id).write(addr, &[data])
+}
+
+#[no_mangle]
+extern "C" fn cluster_mem_read_bd(handle: u32, id: u32, addr: u64, data: &mut u8) {
+    sparse_mem(handle, id).read(addr, std::slice::from_mut(data))
+}
+
+#[no_mangle]
+extern "C" fn cluster_mem_write_bd_bytes(
+    handle: u32,
+    id: u32,
+    addr: u64,
+    data: *const u8,
+    len: u32,
+) {
+    sparse_mem(handle, id).write(addr, unsafe {
+        std::slice::from_raw_parts(data, len as usize)
+    })
+}
+
+#[no_mangle]
+extern "C" fn cluster_mem_read_bd_bytes(handle: u32, id: u32, addr: u64, data: *mut u8, len: u32) {
+    sparse_mem(handle, id).read(addr, unsafe {
+        std::slice::from_raw_parts_mut(data, len as usize)
+    })
+}
+
+#[no_mangle]
+extern "C" fn cluster_reset_core(handle: u32, hartid: u32, boot_addr: u64) {
+    cluster(handle)
+        .get()
+        .reset_core(hartid as usize, boot_addr)
+        .unwrap_or_else(|e| panic!("{}", e));
     println!("reset core to {:#x}!", boot_addr);
 }
 
-#[allow(static_mut_refs)]
+#[no_mangle]
+extern "C" fn cluster_reset_core_default(handle: u32, hartid: u32) {
+    let boot_addr = cluster(handle).get().hart_descs[hartid as usize].reset_vector;
+    cluster_reset_core(handle, hartid, boot_addr)
+}
+
+//pause hart `hartid`, it keeps its architectural state until cluster_resume_core
+#[no_mangle]
+extern "C" fn cluster_halt_core(handle: u32, hartid: u32) {
+    cluster(handle)
+        .get()
+        .halt_core(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+//resume a halted hart, or release a hart held in reset from its reset vector
+#[no_mangle]
+extern "C" fn cluster_resume_core(handle: u32, hartid: u32) {
+    cluster(handle)
+        .get()
+        .resume_core(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+//0: held in reset, 1: running, 2: halted
+#[no_mangle]
+extern "C" fn cluster_core_state(handle: u32, hartid: u32) -> u32 {
+    cluster(handle)
+        .get()
+        .hart_state(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e)) as u32
+}
+
+#[no_mangle]
+extern "C" fn cluster_read_gpr(handle: u32, hartid: u32, idx: u32) -> u64 {
+    cluster(handle)
+        .get()
+        .read_gpr(hartid as usize, idx)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[no_mangle]
+extern "C" fn cluster_write_gpr(handle: u32, hartid: u32, idx: u32, value: u64) {
+    cluster(handle)
+        .get()
+        .write_gpr(hartid as usize, idx, value)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[no_mangle]
+extern "C" fn cluster_read_pc(handle: u32, hartid: u32) -> u64 {
+    cluster(handle)
+        .get()
+        .read_pc(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[no_mangle]
+extern "C" fn cluster_write_pc(handle: u32, hartid: u32, pc: u64) {
+    cluster(handle)
+        .get()
+        .write_pc(hartid as usize, pc)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+//return 1 if `csr` is not accessible
+#[no_mangle]
+extern "C" fn cluster_read_csr(handle: u32, hartid: u32, csr: u32, value: &mut u64) -> u32 {
+    match cluster(handle).get().read_csr(hartid as usize, csr) {
+        Ok(v) => {
+            *value = v;
+            0
+        }
+        Err(e) => {
+            eprintln!("cluster_read_csr: {}", e);
+            1
+        }
+    }
+}
+
+//return 1 if `csr` is not accessible
+#[no_mangle]
+extern "C" fn cluster_write_csr(handle: u32, hartid: u32, csr: u32, value: u64) -> u32 {
+    match cluster(handle).get().write_csr(hartid as usize, csr, value) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("cluster_write_csr: {}", e);
+            1
+        }
+    }
+}
+
+//switch spike-compatible commit log of hart `hartid` on/off
+#[no_mangle]
+extern "C" fn cluster_commit_log(handle: u32, hartid: u32, enable: u32) {
+    cluster(handle)
+        .get()
+        .enable_commit_log(hartid as usize, enable != 0)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+//start/stop buffering retirement records of hart `hartid`
+#[no_mangle]
+extern "C" fn cluster_rvfi_enable(handle: u32, hartid: u32, enable: u32) {
+    cluster(handle)
+        .get()
+        .rvfi(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .enable(enable != 0)
+}
+
+//number of records dropped because the buffer of hart `hartid` was full
+#[no_mangle]
+extern "C" fn cluster_rvfi_dropped(handle: u32, hartid: u32) -> u64 {
+    cluster(handle)
+        .get()
+        .rvfi(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .dropped()
+}
+
+//pop the oldest retirement record of hart `hartid`, return 0 if the buffer is empty
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+extern "C" fn cluster_rvfi_pop(
+    handle: u32,
+    hartid: u32,
+    order: &mut u64,
+    insn: &mut u32,
+    trap: &mut u32,
+    mode: &mut u32,
+    rs1_addr: &mut u32,
+    rs2_addr: &mut u32,
+    rs1_rdata: &mut u64,
+    rs2_rdata: &mut u64,
+    rd_addr: &mut u32,
+    rd_wdata: &mut u64,
+    pc_rdata: &mut u64,
+    pc_wdata: &mut u64,
+    mem_addr: &mut u64,
+    mem_rmask: &mut u32,
+    mem_wmask: &mut u32,
+    mem_rdata: &mut u64,
+    mem_wdata: &mut u64,
+) -> u32 {
+    let r = match cluster(handle)
+        .get()
+        .rvfi(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .pop()
+    {
+        Some(r) => r,
+        None => return 0,
+    };
+    *order = r.order;
+    *insn = r.insn;
+    *trap = r.trap as u32;
+    *mode = r.mode as u32;
+    *rs1_addr = r.rs1_addr;
+    *rs2_addr = r.rs2_addr;
+    *rs1_rdata = r.rs1_rdata;
+    *rs2_rdata = r.rs2_rdata;
+    *rd_addr = r.rd_addr;
+    *rd_wdata = r.rd_wdata;
+    *pc_rdata = r.pc_rdata;
+    *pc_wdata = r.pc_wdata;
+    *mem_addr = r.mem_addr;
+    *mem_rmask = r.mem_rmask;
+    *mem_wmask = r.mem_wmask;
+    *mem_rdata = r.mem_rdata;
+    *mem_wdata = r.mem_wdata;
+    1
+}
+
+//start gdb remote stub on 127.0.0.1:`port`, block until gdb connects if `wait` is not 0
+#[no_mangle]
+extern "C" fn cluster_gdb_listen(handle: u32, port: u32, wait: u32) -> u32 {
+    match cluster(handle).get().gdb_listen(port as u16, wait != 0) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("cluster_gdb_listen: {}", e);
+            1
+        }
+    }
+}
+
+//make hart `hartid` a shadow of an rtl core, it is only stepped by cluster_lockstep_check
+//and reports `depth` preceding instructions at the first divergence
+#[no_mangle]
+extern "C" fn cluster_lockstep_enable(handle: u32, hartid: u32, depth: u32) {
+    cluster(handle)
+        .get()
+        .enable_lockstep(hartid as usize, depth as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[no_mangle]
+extern "C" fn cluster_lockstep_disable(handle: u32, hartid: u32) {
+    cluster(handle)
+        .get()
+        .disable_lockstep(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+//push a retirement event of the rtl core, the shadow hart retires one instruction to compare
+//return 0: match, 1: first divergence, 2: diverged before, 3: shadow does not retire
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+extern "C" fn cluster_lockstep_check(
+    handle: u32,
+    hartid: u32,
+    pc: u64,
+    insn: u32,
+    rd_addr: u32,
+    rd_wdata: u64,
+    mem_addr: u64,
+    mem_wdata: u64,
+    mem_wmask: u32,
+) -> u32 {
+    let rtl = RtlRetire {
+        pc,
+        insn,
+        rd_addr,
+        rd_wdata,
+        mem_addr,
+        mem_wdata,
+        mem_wmask,
+    };
+    let status = cluster(handle)
+        .get()
+        .lockstep_check(hartid as usize, &rtl)
+        .unwrap_or_else(|e| panic!("{}", e));
+    report_watch_hits(handle);
+    status as u32
+}
+
+//watch hits are reported after the cluster is released, so sv can call back into it
+#[cfg(feature = "watch_callback")]
+fn report_watch_hits(handle: u32) {
+    let hits = cluster(handle).get().take_watch_reports();
+    for (hartid, hit) in hits {
+        watch::watch_callback(handle, hartid, &hit)
+    }
+}
+
+#[cfg(not(feature = "watch_callback"))]
+fn report_watch_hits(_handle: u32) {}
+
 #[no_mangle]
 extern "C" fn cluster_run() -> ! {
     extern "C" {
         fn cluster_step();
     }
-    unsafe {
-        loop {
-            for p in &mut CLUSTER.processors {
-                p.step(1);
-            }
-            cluster_step();
+    loop {
+        for handle in local_handles() {
+            cluster(handle).get().step_all();
+            report_watch_hits(handle);
+        }
+        unsafe { cluster_step() };
+    }
+}
+
+#[no_mangle]
+extern "C" fn cluster_run_1step(handle: u32) {
+    cluster(handle).get().step_all();
+    report_watch_hits(handle)
+}
+
+//step hart `hartid` by at most `n` instructions, return why it stops:
+//0: `n` instructions stepped, 1: wfi, 2: trap, 3: halted
+#[no_mangle]
+extern "C" fn cluster_step_core(handle: u32, hartid: u32, n: u64) -> u32 {
+    let status = cluster(handle)
+        .get()
+        .step_core(hartid as usize, n)
+        .unwrap_or_else(|e| panic!("{}", e));
+    report_watch_hits(handle);
+    status as u32
+}
+
+//step every hart by at most `n` instructions, return bitmask of harts stopped early
+#[no_mangle]
+extern "C" fn cluster_step_cores(handle: u32, n: u64) -> u64 {
+    let mask = cluster(handle).get().step_cores(n);
+    report_watch_hits(handle);
+    mask
+}
+
+//advance mtime of clint by `n` ticks, return 1 if not in sim mtime mode
+#[no_mangle]
+extern "C" fn cluster_tick_mtime(handle: u32, n: u64) -> u32 {
+    match cluster(handle).get().tick_mtime(n) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("cluster_tick_mtime: {}", e);
+            1
+        }
+    }
+}
+
+//load `path` into the memories seen by harts in `hartmask`, return the entry point
+#[no_mangle]
+extern "C" fn cluster_load_elf(handle: u32, path: *const c_char, hartmask: u64) -> u64 {
+    let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap();
+    cluster(handle)
+        .get()
+        .load_elf(path, hartmask)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[no_mangle]
+extern "C" fn cluster_symbol_addr(handle: u32, name: *const c_char, addr: &mut u64) -> u32 {
+    let name = unsafe { CStr::from_ptr(name) }.to_str().unwrap();
+    match cluster(handle).get().symbol_addr(name) {
+        Ok(a) => {
+            *addr = a;
+            0
+        }
+        Err(e) => {
+            eprintln!("cluster_symbol_addr: {}", e);
+            1
         }
     }
 }
 
-#[allow(static_mut_refs)]
+//watch [base, base + size) on harts in `hartmask`, `kinds` is 1: read | 2: write | 4: exec
+//hits are reported by cluster_watch_hit with feature watch_callback, printed otherwise
+//return the watchpoint id
+#[no_mangle]
+extern "C" fn cluster_watch_add(
+    handle: u32,
+    hartmask: u64,
+    base: u64,
+    size: u64,
+    kinds: u32,
+    halt: u32,
+) -> u32 {
+    cluster(handle)
+        .get()
+        .add_watch(hartmask, base, size, kinds, halt != 0)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+//`size` 0 means the size of symbol `name`
+#[no_mangle]
+extern "C" fn cluster_watch_add_symbol(
+    handle: u32,
+    hartmask: u64,
+    name: *const c_char,
+    size: u64,
+    kinds: u32,
+    halt: u32,
+) -> u32 {
+    let name = unsafe { CStr::from_ptr(name) }.to_str().unwrap();
+    cluster(handle)
+        .get()
+        .add_watch_symbol(hartmask, name, size, kinds, halt != 0)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[no_mangle]
+extern "C" fn cluster_watch_remove(handle: u32, id: u32) {
+    cluster(handle).get().remove_watch(id)
+}
+
+//trace bus transactions of all harts to csv `path`, previous trace is closed
 #[no_mangle]
-extern "C" fn cluster_run_1step() {
-    unsafe {
-        for p in &mut CLUSTER.processors {
-            p.step(1);
+extern "C" fn cluster_bus_trace_start(handle: u32, path: *const c_char) -> u32 {
+    let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap();
+    match cluster(handle).get().start_bus_trace(path) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("cluster_bus_trace_start: {}", e);
+            1
         }
     }
 }
 
-#[allow(static_mut_refs)]
 #[no_mangle]
-extern "C" fn cluster_statics() {
-    unsafe {
-        for p in &CLUSTER.processors {
-            println!("{}", p.state().to_string())
+extern "C" fn cluster_bus_trace_stop(handle: u32) {
+    cluster(handle).get().stop_bus_trace()
+}
+
+//save registers and csrs of all harts, clint and sparse memories to `path`
+//with feature sv_checkpoint, sv is called by cluster_sv_checkpoint to save its memories alongside
+#[no_mangle]
+extern "C" fn cluster_save_checkpoint(handle: u32, path: *const c_char) -> u32 {
+    let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap();
+    if let Err(e) = cluster(handle).get().save_checkpoint(path) {
+        eprintln!("cluster_save_checkpoint: {}", e);
+        return 1;
+    }
+    #[cfg(feature = "sv_checkpoint")]
+    checkpoint::sv_checkpoint(handle, path, true);
+    0
+}
+
+#[no_mangle]
+extern "C" fn cluster_restore_checkpoint(handle: u32, path: *const c_char) -> u32 {
+    let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap();
+    if let Err(e) = cluster(handle).get().restore_checkpoint(path) {
+        eprintln!("cluster_restore_checkpoint: {}", e);
+        return 1;
+    }
+    #[cfg(feature = "sv_checkpoint")]
+    checkpoint::sv_checkpoint(handle, path, false);
+    0
+}
+
+#[no_mangle]
+extern "C" fn cluster_stat_instret(handle: u32, hartid: u32) -> u64 {
+    cluster(handle)
+        .get()
+        .hart_stats(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .instret
+}
+
+#[no_mangle]
+extern "C" fn cluster_stat_cycles(handle: u32, hartid: u32) -> u64 {
+    cluster(handle)
+        .get()
+        .hart_stats(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .cycles
+}
+
+#[no_mangle]
+extern "C" fn cluster_stat_wfi_cycles(handle: u32, hartid: u32) -> u64 {
+    cluster(handle)
+        .get()
+        .hart_stats(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .wfi_cycles
+}
+
+#[no_mangle]
+extern "C" fn cluster_stat_traps(handle: u32, hartid: u32) -> u64 {
+    cluster(handle)
+        .get()
+        .hart_stats(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .traps
+}
+
+//cycles spent in `privilege`, 0: U, 1: S, 3: M
+#[no_mangle]
+extern "C" fn cluster_stat_privilege_cycles(handle: u32, hartid: u32, privilege: u32) -> u64 {
+    cluster(handle)
+        .get()
+        .hart_stats(hartid as usize)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .privilege_cycles
+        .get(privilege as u8)
+        .unwrap_or_else(|| panic!("invalid privilege {}!", privilege))
+}
+
+//dump statistics of all harts to `path` in json
+#[no_mangle]
+extern "C" fn cluster_stats_dump(handle: u32, path: *const c_char) -> u32 {
+    let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap();
+    match cluster(handle).get().stats().dump(path) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("cluster_stats_dump: {}", e);
+            1
         }
     }
 }
+
+#[no_mangle]
+extern "C" fn cluster_statics(handle: u32) {
+    cluster(handle).get().statics()
+}
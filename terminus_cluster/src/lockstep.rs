@@ -0,0 +1,270 @@
+use crate::commit_log::format_commit;
+use crate::retire::Retired;
+use std::collections::VecDeque;
+
+//retirement event of the rtl core, `mem_wmask` is relative to `mem_addr`, 0 if no store
+pub struct RtlRetire {
+    pub pc: u64,
+    pub insn: u32,
+    pub rd_addr: u32,
+    pub rd_wdata: u64,
+    pub mem_addr: u64,
+    pub mem_wdata: u64,
+    pub mem_wmask: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(u32)]
+pub enum LockstepStatus {
+    Match = 0,
+    //the first divergence, reported with preceding instructions
+    Mismatch = 1,
+    //diverged before, shadow hart is not stepped any more
+    Diverged = 2,
+    //shadow hart does not retire, e.g. it waits in wfi for an interrupt the rtl core took
+    Stalled = 3,
+}
+
+pub struct Lockstep {
+    xlen: u32,
+    depth: usize,
+    //commit lines of preceding instructions
+    window: VecDeque<String>,
+    checked: u64,
+    diverged: bool,
+}
+
+impl Lockstep {
+    pub fn new(xlen: u32, depth: usize) -> Lockstep {
+        Lockstep {
+            xlen,
+            depth,
+            window: VecDeque::with_capacity(depth),
+            checked: 0,
+            diverged: false,
+        }
+    }
+
+    pub fn diverged(&self) -> bool {
+        self.diverged
+    }
+
+    pub fn check(&mut self, iss: &Retired, rtl: &RtlRetire) -> LockstepStatus {
+        let line = format_commit(iss, self.xlen);
+        if let Some(diff) = self.compare(iss, rtl) {
+            self.diverged = true;
+            eprintln!(
+                "lockstep: core{} diverges at instruction {}: {}",
+                iss.hartid, self.checked, diff
+            );
+            eprintln!("lockstep: preceding instructions:");
+            for l in &self.window {
+                eprintln!("  {}", l);
+            }
+            eprintln!("lockstep: shadow: {}", line);
+            return LockstepStatus::Mismatch;
+        }
+        if self.window.len() >= self.depth {
+            self.window.pop_front();
+        }
+        self.window.push_back(line);
+        self.checked += 1;
+        LockstepStatus::Match
+    }
+
+    //the shadow hart did not retire in `steps` steps, it is taken as diverged
+    pub fn stall(&mut self, hartid: usize, steps: usize) -> LockstepStatus {
+        self.diverged = true;
+        eprintln!(
+            "lockstep: core{} does not retire in {} steps at instruction {}",
+            hartid, steps, self.checked
+        );
+        eprintln!("lockstep: preceding instructions:");
+        for l in &self.window {
+            eprintln!("  {}", l);
+        }
+        LockstepStatus::Stalled
+    }
+
+    fn compare(&self, iss: &Retired, rtl: &RtlRetire) -> Option<String> {
+        if iss.pc != rtl.pc {
+            return Some(format!("pc shadow {:#x}, rtl {:#x}", iss.pc, rtl.pc));
+        }
+        if iss.insn != rtl.insn {
+            return Some(format!("insn shadow {:#x}, rtl {:#x}", iss.insn, rtl.insn));
+        }
+        let (rd_addr, rd_wdata) = iss.rd.unwrap_or((0, 0));
+        if rd_addr != rtl.rd_addr || (rd_addr != 0 && rd_wdata != rtl.rd_wdata) {
+            return Some(format!(
+                "rd shadow x{} = {:#x}, rtl x{} = {:#x}",
+                rd_addr, rd_wdata, rtl.rd_addr, rtl.rd_wdata
+            ));
+        }
+        let (mem_addr, mem_wdata, mem_wmask) = iss
+            .mem
+            .iter()
+            .find(|a| a.write)
+            .map_or((0, 0, 0), |a| (a.addr, a.data, (1u32 << a.size) - 1));
+        let data_mask = (0..8)
+            .filter(|i| mem_wmask & (1 << i) != 0)
+            .fold(0u64, |m, i| m | (0xff << (i * 8)));
+        if mem_wmask != rtl.mem_wmask
+            || (mem_wmask != 0
+                && (mem_addr != rtl.mem_addr || mem_wdata & data_mask != rtl.mem_wdata & data_mask))
+        {
+            return Some(format!(
+                "store shadow {:#x} = {:#x} mask {:#x}, rtl {:#x} = {:#x} mask {:#x}",
+                mem_addr, mem_wdata, mem_wmask, rtl.mem_addr, rtl.mem_wdata, rtl.mem_wmask
+            ));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::MemAccess;
+
+    //sw a1, 4(a0)
+    fn store() -> Retired {
+        Retired {
+            hartid: 0,
+            pc: 0x8000_0000,
+            next_pc: 0x8000_0004,
+            insn: 0x00b5_2223,
+            privilege: 3,
+            rs1: (10, 0x8000_1000),
+            rs2: (11, 0x1234_5678),
+            rd: Some((0, 0)),
+            trap: false,
+            mem: vec![MemAccess {
+                addr: 0x8000_1004,
+                size: 4,
+                data: 0xdead_beef_1234_5678,
+                write: true,
+            }],
+        }
+    }
+
+    fn rtl_store() -> RtlRetire {
+        RtlRetire {
+            pc: 0x8000_0000,
+            insn: 0x00b5_2223,
+            rd_addr: 0,
+            rd_wdata: 0x55,
+            mem_addr: 0x8000_1004,
+            mem_wdata: 0x1234_5678,
+            mem_wmask: 0xf,
+        }
+    }
+
+    //addi a0, a0, 1
+    fn addi() -> (Retired, RtlRetire) {
+        let iss = Retired {
+            insn: 0x0015_0513,
+            rd: Some((10, 0x8000_1001)),
+            mem: vec![],
+            ..store()
+        };
+        let rtl = RtlRetire {
+            insn: 0x0015_0513,
+            rd_addr: 10,
+            rd_wdata: 0x8000_1001,
+            mem_addr: 0,
+            mem_wdata: 0,
+            mem_wmask: 0,
+            ..rtl_store()
+        };
+        (iss, rtl)
+    }
+
+    #[test]
+    fn compare_match() {
+        let l = Lockstep::new(32, 4);
+        //rd data of x0 and store data out of the mask are ignored
+        assert_eq!(l.compare(&store(), &rtl_store()), None);
+        let (iss, rtl) = addi();
+        assert_eq!(l.compare(&iss, &rtl), None);
+    }
+
+    #[test]
+    fn compare_pc_insn() {
+        let l = Lockstep::new(32, 4);
+        let rtl = RtlRetire {
+            pc: 0x8000_0004,
+            ..rtl_store()
+        };
+        assert!(l.compare(&store(), &rtl).unwrap().starts_with("pc"));
+        let rtl = RtlRetire {
+            insn: 0x00b5_2023,
+            ..rtl_store()
+        };
+        assert!(l.compare(&store(), &rtl).unwrap().starts_with("insn"));
+    }
+
+    #[test]
+    fn compare_rd() {
+        let l = Lockstep::new(32, 4);
+        let (iss, rtl) = addi();
+        let wdata = RtlRetire {
+            rd_wdata: 0x8000_1000,
+            ..addi().1
+        };
+        assert!(l.compare(&iss, &wdata).unwrap().starts_with("rd"));
+        let addr = RtlRetire { rd_addr: 11, ..rtl };
+        assert!(l.compare(&iss, &addr).unwrap().starts_with("rd"));
+    }
+
+    #[test]
+    fn compare_store() {
+        let l = Lockstep::new(32, 4);
+        for rtl in [
+            RtlRetire {
+                mem_addr: 0x8000_1000,
+                ..rtl_store()
+            },
+            RtlRetire {
+                mem_wdata: 0x1234_5679,
+                ..rtl_store()
+            },
+            RtlRetire {
+                mem_wmask: 0x3,
+                ..rtl_store()
+            },
+        ] {
+            assert!(l.compare(&store(), &rtl).unwrap().starts_with("store"));
+        }
+        //a store missed by the shadow hart
+        let (iss, rtl) = addi();
+        let rtl = RtlRetire {
+            mem_addr: 0x8000_1004,
+            mem_wdata: 0x1,
+            mem_wmask: 0x1,
+            ..rtl
+        };
+        assert!(l.compare(&iss, &rtl).unwrap().starts_with("store"));
+    }
+
+    #[test]
+    fn check_diverges() {
+        let mut l = Lockstep::new(32, 2);
+        let (iss, rtl) = addi();
+        for _ in 0..3 {
+            assert_eq!(l.check(&iss, &rtl), LockstepStatus::Match);
+        }
+        assert_eq!(l.window.len(), 2);
+        assert!(!l.diverged());
+        assert_eq!(l.check(&iss, &rtl_store()), LockstepStatus::Mismatch);
+        assert!(l.diverged());
+    }
+
+    #[test]
+    fn stall_diverges() {
+        let mut l = Lockstep::new(32, 2);
+        let (iss, rtl) = addi();
+        assert_eq!(l.check(&iss, &rtl), LockstepStatus::Match);
+        assert_eq!(l.stall(0, 16), LockstepStatus::Stalled);
+        assert!(l.diverged());
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const PAGE_SHIFT: u64 = 12;
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+const PAGE_MASK: u64 = PAGE_SIZE as u64 - 1;
+
+type Pages = HashMap<u64, Box<[u8; PAGE_SIZE]>>;
+
+//Pages are allocated on first write, unwritten bytes read as 0.
+//Backdoor dpi calls may come from other threads than the harts, so pages are locked per access.
+#[derive(Default)]
+pub struct SparseMem {
+    pages: Mutex<Pages>,
+}
+
+impl SparseMem {
+    pub fn new() -> SparseMem {
+        SparseMem::default()
+    }
+
+    pub fn write(&self, addr: u64, data: &[u8]) {
+        write_pages(&mut self.pages.lock().unwrap(), addr, data)
+    }
+
+    pub fn read(&self, addr: u64, data: &mut [u8]) {
+        let pages = self.pages.lock().unwrap();
+        let mut addr = addr;
+        let mut data = data;
+        while !data.is_empty() {
+            let offset = (addr & PAGE_MASK) as usize;
+            let len = std::cmp::min(PAGE_SIZE - offset, data.len());
+            match pages.get(&(addr >> PAGE_SHIFT)) {
+                Some(page) => data[..len].copy_from_slice(&page[offset..offset + len]),
+                None => data[..len].fill(0),
+            }
+            addr += len as u64;
+            data = &mut data[len..];
+        }
+    }
+
+    //written pages as (page address, data), in address order
+    pub fn pages(&self) -> Vec<(u64, Vec<u8>)> {
+        let mut pages = self
+            .pages
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(n, page)| (n << PAGE_SHIFT, page.to_vec()))
+            .collect::<Vec<_>>();
+        pages.sort_by_key(|(addr, _)| *addr);
+        pages
+    }
+
+    //drop all contents then write `pages`
+    pub fn restore(&self, pages: &[(u64, Vec<u8>)]) {
+        let mut cur = self.pages.lock().unwrap();
+        cur.clear();
+        for (addr, data) in pages {
+            write_pages(&mut cur, *addr, data);
+        }
+    }
+}
+
+fn write_pages(pages: &mut Pages, addr: u64, data: &[u8]) {
+    let mut addr = addr;
+    let mut data = data;
+    while !data.is_empty() {
+        let offset = (addr & PAGE_MASK) as usize;
+        let len = std::cmp::min(PAGE_SIZE - offset, data.len());
+        let page = pages
+            .entry(addr >> PAGE_SHIFT)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        page[offset..offset + len].copy_from_slice(&data[..len]);
+        addr += len as u64;
+        data = &data[len..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unwritten_reads_zero() {
+        let mem = SparseMem::new();
+        let mut data = [0xff; 8];
+        mem.read(0x8000_0000, &mut data);
+        assert_eq!(data, [0; 8]);
+        assert!(mem.pages().is_empty());
+    }
+
+    #[test]
+    fn page_crossing_access() {
+        let mem = SparseMem::new();
+        let data = (0..16).collect::<Vec<u8>>();
+        mem.write(0x1ff8, &data);
+        let mut read = [0; 16];
+        mem.read(0x1ff8, &mut read);
+        assert_eq!(read[..], data[..]);
+        assert_eq!(
+            mem.pages()
+                .iter()
+                .map(|(addr, _)| *addr)
+                .collect::<Vec<_>>(),
+            vec![0x1000, 0x2000]
+        );
+        //read across into an unwritten page
+        let mut read = [0xff; 8];
+        mem.read(0x2ffc, &mut read);
+        assert_eq!(read, [0; 8]);
+    }
+
+    #[test]
+    fn pages_restore() {
+        let mem = SparseMem::new();
+        mem.write(0x5000, &[1, 2, 3, 4]);
+        mem.write(0x1004, &[5, 6]);
+        let pages = mem.pages();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].0, 0x1000);
+        assert_eq!(pages[0].1.len(), PAGE_SIZE);
+        assert_eq!(pages[1].0, 0x5000);
+
+        let other = SparseMem::new();
+        other.write(0x9000, &[7]);
+        other.restore(&pages);
+        assert_eq!(other.pages(), pages);
+        let mut data = [0xff; 1];
+        other.read(0x9000, &mut data);
+        assert_eq!(data, [0]);
+        let mut data = [0; 4];
+        other.read(0x5000, &mut data);
+        assert_eq!(data, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let mem = std::sync::Arc::new(SparseMem::new());
+        let writer = {
+            let mem = mem.clone();
+            std::thread::spawn(move || {
+                for i in 0..256u64 {
+                    mem.write(0x8000_0000 + i * 8, &i.to_le_bytes());
+                }
+            })
+        };
+        writer.join().unwrap();
+        let mut data = [0; 8];
+        mem.read(0x8000_0000 + 255 * 8, &mut data);
+        assert_eq!(u64::from_le_bytes(data), 255);
+    }
+}
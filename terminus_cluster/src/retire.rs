@@ -0,0 +1,406 @@
+use crate::bus::{CoreBus, MemAccess};
+use terminus::global::{RegT, XLen};
+use terminus::processor::Processor;
+
+pub struct Retired {
+    pub hartid: usize,
+    pub pc: RegT,
+    pub next_pc: RegT,
+    pub insn: u32,
+    pub privilege: u8,
+    //source registers and their values before execution, x0 if not used
+    pub rs1: (u32, RegT),
+    pub rs2: (u32, RegT),
+    //`None` if the instruction trapped
+    pub rd: Option<(u32, RegT)>,
+    pub trap: bool,
+    //data accesses of the instruction itself, at most one read and one write
+    pub mem: Vec<MemAccess>,
+}
+
+//snapshot taken before stepping one instruction
+pub struct RetireProbe {
+    pc: RegT,
+    privilege: u8,
+    xregs: [RegT; 32],
+    retired: u64,
+}
+
+impl RetireProbe {
+    pub fn before(p: &Processor, bus: &CoreBus) -> RetireProbe {
+        let state = p.state();
+        let mut xregs = [0; 32];
+        for (i, x) in xregs.iter_mut().enumerate() {
+            *x = *state.xreg(i as RegT);
+        }
+        bus.start_record();
+        RetireProbe {
+            pc: *state.pc(),
+            privilege: *state.privilege() as u8,
+            xregs,
+            retired: state.insns_cnt(),
+        }
+    }
+
+    pub fn after(self, p: &Processor, bus: &CoreBus) -> Retired {
+        let state = p.state();
+        let insn = *state.ir();
+        let trap = state.insns_cnt() == self.retired;
+        let rv64 = state.config().xlen == XLen::X64;
+        let accesses = bus.take_record();
+        let mem = if trap {
+            vec![]
+        } else {
+            insn_accesses(insn, rv64, &accesses)
+        };
+        let (rs1, rs2) = decode_rs(insn, rv64);
+        let rd = Some(decode_rd(insn, rv64))
+            .filter(|&rd| !trap && rd != 0)
+            .map(|rd| (rd as u32, *state.xreg(rd as RegT)));
+        Retired {
+            hartid: state.hartid(),
+            pc: self.pc,
+            next_pc: *state.pc(),
+            insn,
+            privilege: self.privilege,
+            rs1: (rs1 as u32, self.xregs[rs1]),
+            rs2: (rs2 as u32, self.xregs[rs2]),
+            rd,
+            trap,
+            mem,
+        }
+    }
+}
+
+pub fn insn_len(insn: u32) -> usize {
+    if insn & 0x3 == 0x3 {
+        4
+    } else {
+        2
+    }
+}
+
+//integer rd of instructions, 0 if not written, so writes of an unchanged value are reported too
+fn decode_rd(insn: u32, rv64: bool) -> usize {
+    let bits = |hi: u32, lo: u32| ((insn >> lo) & ((1 << (hi - lo + 1)) - 1)) as usize;
+    if insn_len(insn) == 4 {
+        return match insn & 0x7f {
+            //lui, auipc, jal, jalr, load, op-imm, op, op-imm-32, op-32, amo
+            0x37 | 0x17 | 0x6f | 0x67 | 0x03 | 0x13 | 0x33 | 0x1b | 0x3b | 0x2f => bits(11, 7),
+            //csr instructions
+            0x73 if bits(14, 12) != 0 => bits(11, 7),
+            //feq, flt, fle, fcvt to integer, fmv.x, fclass
+            0x53 if matches!(bits(31, 27), 0x14 | 0x18 | 0x1c) => bits(11, 7),
+            _ => 0,
+        };
+    }
+    let (r, r2) = (bits(11, 7), bits(6, 2));
+    let (rp, rp2) = (8 + bits(9, 7), 8 + bits(4, 2));
+    match (insn & 0x3, bits(15, 13)) {
+        //c.addi4spn, c.lw
+        (0, 0) | (0, 2) => rp2,
+        //c.ld in rv64, c.flw in rv32
+        (0, 3) if rv64 => rp2,
+        //c.addi, c.li, c.lui, c.addi16sp
+        (1, 0) | (1, 2) | (1, 3) => r,
+        //c.addiw in rv64, c.jal in rv32
+        (1, 1) => {
+            if rv64 {
+                r
+            } else {
+                1
+            }
+        }
+        //c.srli, c.srai, c.andi, c.sub, c.xor, c.or, c.and, c.subw, c.addw
+        (1, 4) => rp,
+        //c.slli, c.lwsp
+        (2, 0) | (2, 2) => r,
+        //c.ldsp in rv64, c.flwsp in rv32
+        (2, 3) if rv64 => r,
+        //c.jr, c.mv, c.ebreak, c.jalr, c.add
+        (2, 4) => match (bits(12, 12), r2) {
+            (0, 0) => 0,
+            (0, _) => r,
+            (_, 0) if r == 0 => 0,
+            (_, 0) => 1,
+            _ => r,
+        },
+        _ => 0,
+    }
+}
+
+//data accesses of `insn` among all `accesses` of its step,
+//page table walks and a/d updates go before the access of the instruction
+fn insn_accesses(insn: u32, rv64: bool, accesses: &[MemAccess]) -> Vec<MemAccess> {
+    match decode_mem(insn, rv64) {
+        Some((load, store, size)) => [(load, false), (store, true)]
+            .iter()
+            .filter(|(used, _)| *used)
+            .filter_map(|(_, write)| {
+                accesses
+                    .iter()
+                    .rev()
+                    .find(|a| a.write == *write && a.size == size)
+                    .copied()
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
+//(load, store, size) of memory instructions
+fn decode_mem(insn: u32, rv64: bool) -> Option<(bool, bool, usize)> {
+    let bits = |hi: u32, lo: u32| ((insn >> lo) & ((1 << (hi - lo + 1)) - 1)) as usize;
+    //c.ld, c.sd, c.ldsp, c.sdsp in rv64, c.flw, c.fsw, c.flwsp, c.fswsp in rv32
+    let xsize = if rv64 { 8 } else { 4 };
+    if insn_len(insn) == 4 {
+        return match insn & 0x7f {
+            //load, store
+            0x03 => Some((true, false, 1 << (bits(14, 12) & 0x3))),
+            0x23 => Some((false, true, 1 << (bits(14, 12) & 0x3))),
+            //flw, fld, fsw, fsd
+            0x07 => Some((true, false, 1 << bits(14, 12))),
+            0x27 => Some((false, true, 1 << bits(14, 12))),
+            //lr, sc, amo
+            0x2f => match bits(31, 27) {
+                0x2 => Some((true, false, 1 << bits(14, 12))),
+                0x3 => Some((false, true, 1 << bits(14, 12))),
+                _ => Some((true, true, 1 << bits(14, 12))),
+            },
+            _ => None,
+        };
+    }
+    match (insn & 0x3, bits(15, 13)) {
+        //c.fld, c.lw, c.fldsp, c.lwsp
+        (0, 1) | (2, 1) => Some((true, false, 8)),
+        (0, 2) | (2, 2) => Some((true, false, 4)),
+        (0, 3) | (2, 3) => Some((true, false, xsize)),
+        //c.fsd, c.sw, c.fsdsp, c.swsp
+        (0, 5) | (2, 5) => Some((false, true, 8)),
+        (0, 6) | (2, 6) => Some((false, true, 4)),
+        (0, 7) | (2, 7) => Some((false, true, xsize)),
+        _ => None,
+    }
+}
+
+//(rs1, rs2) of integer instructions, 0 if not used
+fn decode_rs(insn: u32, rv64: bool) -> (usize, usize) {
+    let bits = |hi: u32, lo: u32| ((insn >> lo) & ((1 << (hi - lo + 1)) - 1)) as usize;
+    if insn_len(insn) == 4 {
+        let (rs1, rs2) = (bits(19, 15), bits(24, 20));
+        return match insn & 0x7f {
+            //branch, store, op, op-32, amo
+            0x63 | 0x23 | 0x33 | 0x3b | 0x2f => (rs1, rs2),
+            //jalr, load, op-imm, op-imm-32, fp load and store
+            0x67 | 0x03 | 0x13 | 0x1b | 0x07 | 0x27 => (rs1, 0),
+            //fcvt from integer, fmv from integer
+            0x53 if matches!(bits(31, 27), 0x1a | 0x1e) => (rs1, 0),
+            //csrrw, csrrs, csrrc
+            0x73 if (1..=3).contains(&bits(14, 12)) => (rs1, 0),
+            _ => (0, 0),
+        };
+    }
+    let (r, r2) = (bits(11, 7), bits(6, 2));
+    let (rp, rp2) = (8 + bits(9, 7), 8 + bits(4, 2));
+    match (insn & 0x3, bits(15, 13)) {
+        //c.addi4spn
+        (0, 0) => (2, 0),
+        //c.lw, c.ld, c.flw, c.fld
+        (0, 1..=3) => (rp, 0),
+        //c.sw
+        (0, 6) => (rp, rp2),
+        //c.sd in rv64, c.fsw in rv32
+        (0, 7) => (rp, if rv64 { rp2 } else { 0 }),
+        //c.fsd, c.fsw
+        (0, 5) => (rp, 0),
+        //c.addi
+        (1, 0) => (r, 0),
+        //c.addiw, c.jal in rv32
+        (1, 1) => (if rv64 { r } else { 0 }, 0),
+        //c.addi16sp, c.lui
+        (1, 3) => (if r == 2 { 2 } else { 0 }, 0),
+        //c.srli, c.srai, c.andi
+        (1, 4) if bits(11, 10) != 3 => (rp, 0),
+        //c.sub, c.xor, c.or, c.and, c.subw, c.addw
+        (1, 4) => (rp, rp2),
+        //c.beqz, c.bnez
+        (1, 6) | (1, 7) => (rp, 0),
+        //c.slli
+        (2, 0) => (r, 0),
+        //c.lwsp, c.ldsp, c.fldsp, c.flwsp
+        (2, 1..=3) => (2, 0),
+        //c.jr, c.mv, c.ebreak, c.jalr, c.add
+        (2, 4) => match (bits(12, 12), r2) {
+            (0, 0) => (r, 0),
+            (0, _) => (0, r2),
+            (_, 0) => (r, 0),
+            _ => (r, r2),
+        },
+        //c.swsp
+        (2, 6) => (2, r2),
+        //c.sdsp in rv64, c.fswsp in rv32
+        (2, 7) => (2, if rv64 { r2 } else { 0 }),
+        //c.fsdsp
+        (2, 5) => (2, 0),
+        _ => (0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //(insn, rd in rv32, rd in rv64)
+    #[rustfmt::skip]
+    const RD: &[(u32, usize, usize)] = &[
+        //c.jal in rv32, c.addiw a0, 1 in rv64
+        (0x2505, 1, 10),
+        //c.jalr a0, c.ebreak, c.jr a0, c.mv a0, a1, c.add a0, a1
+        (0x9502, 1, 1), (0x9002, 0, 0), (0x8502, 0, 0), (0x852e, 10, 10), (0x952e, 10, 10),
+        //c.flw fa0, 0(a1) in rv32, c.ld a0, 0(a1) in rv64
+        (0x6188, 0, 10),
+        //c.flwsp fa0, 0(sp) in rv32, c.ldsp a0, 0(sp) in rv64
+        (0x6502, 0, 10),
+        //c.lw a0, 0(a1)
+        (0x4188, 10, 10),
+        //amoadd.w a0, a2, (a1), lr.d a0, (a1), sc.w a0, a2, (a1)
+        (0x00c5_a52f, 10, 10), (0x1005_b52f, 10, 10), (0x18c5_a52f, 10, 10),
+        //fmv.x.w a0, fa1, fclass.s a0, fa1, fcvt.w.s a0, fa1, feq.s a0, fa1, fa2
+        (0xe005_8553, 10, 10), (0xe005_9553, 10, 10), (0xc005_9553, 10, 10), (0xa0c5_a553, 10, 10),
+        //fadd.s fa0, fa1, fa2, fmv.w.x fa0, a1, flw fa0, 0(a1)
+        (0x00c5_f553, 0, 0), (0xf005_8553, 0, 0), (0x0005_a507, 0, 0),
+        //csrrw a0, mstatus, a1
+        (0x3005_9573, 10, 10),
+    ];
+
+    type Mem = Option<(bool, bool, usize)>;
+    type Rs = (usize, usize);
+
+    //(insn, (load, store, size) in rv32, in rv64)
+    #[rustfmt::skip]
+    const MEM: &[(u32, Mem, Mem)] = &[
+        //c.flw fa0, 0(a1) in rv32, c.ld a0, 0(a1) in rv64
+        (0x6188, Some((true, false, 4)), Some((true, false, 8))),
+        //c.fsw fa0, 0(a1) in rv32, c.sd a0, 0(a1) in rv64
+        (0xe188, Some((false, true, 4)), Some((false, true, 8))),
+        //c.flwsp fa0, 0(sp) in rv32, c.ldsp a0, 0(sp) in rv64
+        (0x6502, Some((true, false, 4)), Some((true, false, 8))),
+        //c.lw a0, 0(a1), c.jalr a0
+        (0x4188, Some((true, false, 4)), Some((true, false, 4))),
+        (0x9502, None, None),
+        //amoadd.w a0, a2, (a1), amoswap.d a0, a2, (a1)
+        (0x00c5_a52f, Some((true, true, 4)), Some((true, true, 4))),
+        (0x08c5_b52f, Some((true, true, 8)), Some((true, true, 8))),
+        //lr.d a0, (a1), sc.w a0, a2, (a1)
+        (0x1005_b52f, Some((true, false, 8)), Some((true, false, 8))),
+        (0x18c5_a52f, Some((false, true, 4)), Some((false, true, 4))),
+        //lbu a0, 0(a1), sb a2, 1(a1), flw fa0, 0(a1), fsd fa0, 8(a1)
+        (0x0005_c503, Some((true, false, 1)), Some((true, false, 1))),
+        (0x00c5_80a3, Some((false, true, 1)), Some((false, true, 1))),
+        (0x0005_a507, Some((true, false, 4)), Some((true, false, 4))),
+        (0x00a5_b427, Some((false, true, 8)), Some((false, true, 8))),
+        //fadd.s fa0, fa1, fa2
+        (0x00c5_f553, None, None),
+    ];
+
+    //(insn, (rs1, rs2) in rv32, in rv64)
+    #[rustfmt::skip]
+    const RS: &[(u32, Rs, Rs)] = &[
+        //c.fsw fa0, 0(a1) in rv32, c.sd a0, 0(a1) in rv64
+        (0xe188, (11, 0), (11, 10)),
+        //c.fswsp fa0, 0(sp) in rv32, c.sdsp a0, 0(sp) in rv64
+        (0xe02a, (2, 0), (2, 10)),
+        //c.jal in rv32, c.addiw a0, 1 in rv64
+        (0x2505, (0, 0), (10, 0)),
+        //c.jr a0, c.mv a0, a1, c.add a0, a1
+        (0x8502, (10, 0), (10, 0)), (0x852e, (0, 11), (0, 11)), (0x952e, (10, 11), (10, 11)),
+        //sc.w a0, a2, (a1), sb a2, 1(a1)
+        (0x18c5_a52f, (11, 12), (11, 12)), (0x00c5_80a3, (11, 12), (11, 12)),
+        //flw fa0, 0(a1), fsd fa0, 8(a1)
+        (0x0005_a507, (11, 0), (11, 0)), (0x00a5_b427, (11, 0), (11, 0)),
+        //fmv.w.x fa0, a1, fcvt.s.w fa0, a1, fmv.x.w a0, fa1
+        (0xf005_8553, (11, 0), (11, 0)), (0xd005_f553, (11, 0), (11, 0)), (0xe005_8553, (0, 0), (0, 0)),
+    ];
+
+    fn access(addr: u64, size: usize, data: u64, write: bool) -> MemAccess {
+        MemAccess {
+            addr,
+            size,
+            data,
+            write,
+        }
+    }
+
+    fn addrs(accesses: &[MemAccess]) -> Vec<(u64, bool)> {
+        accesses.iter().map(|a| (a.addr, a.write)).collect()
+    }
+
+    #[test]
+    fn accesses_after_page_walk() {
+        //sv39 walk of 3 ptes and an a/d update, all 8 bytes like the access itself
+        let walk = [
+            access(0x8000_1000, 8, 0x2000_0401, false),
+            access(0x8000_2008, 8, 0x2000_0801, false),
+            access(0x8000_3010, 8, 0x2000_0c0f, false),
+            access(0x8000_3010, 8, 0x2000_0ccf, true),
+        ];
+        //ld a0, 0(a1), as c.ld
+        let mut accesses = walk.to_vec();
+        accesses.push(access(0x8000_4000, 8, 0x1234, false));
+        assert_eq!(
+            addrs(&insn_accesses(0x6188, true, &accesses)),
+            vec![(0x8000_4000, false)]
+        );
+        //sd a0, 0(a1), as c.sd, the walk writes a pte before the store
+        let mut accesses = walk.to_vec();
+        accesses.push(access(0x8000_4000, 8, 0x1234, true));
+        assert_eq!(
+            addrs(&insn_accesses(0xe188, true, &accesses)),
+            vec![(0x8000_4000, true)]
+        );
+        //amoswap.d a0, a2, (a1)
+        let mut accesses = walk.to_vec();
+        accesses.push(access(0x8000_4000, 8, 0x1234, false));
+        accesses.push(access(0x8000_4000, 8, 0x5678, true));
+        assert_eq!(
+            addrs(&insn_accesses(0x08c5_b52f, true, &accesses)),
+            vec![(0x8000_4000, false), (0x8000_4000, true)]
+        );
+        //sv32 ptes are 4 bytes like lw a0, 4(a1)
+        let accesses = [
+            access(0x8000_1000, 4, 0x2000_0401, false),
+            access(0x8000_2008, 4, 0x2000_08cf, false),
+            access(0x8000_4004, 4, 0x1234, false),
+        ];
+        assert_eq!(
+            addrs(&insn_accesses(0x0045_a503, false, &accesses)),
+            vec![(0x8000_4004, false)]
+        );
+        //not a memory instruction
+        assert!(insn_accesses(0x852e, false, &accesses).is_empty());
+    }
+
+    #[test]
+    fn rd() {
+        for &(insn, rv32, rv64) in RD {
+            assert_eq!(decode_rd(insn, false), rv32, "rv32 {:#x}", insn);
+            assert_eq!(decode_rd(insn, true), rv64, "rv64 {:#x}", insn);
+        }
+    }
+
+    #[test]
+    fn mem() {
+        for &(insn, rv32, rv64) in MEM {
+            assert_eq!(decode_mem(insn, false), rv32, "rv32 {:#x}", insn);
+            assert_eq!(decode_mem(insn, true), rv64, "rv64 {:#x}", insn);
+        }
+    }
+
+    #[test]
+    fn rs() {
+        for &(insn, rv32, rv64) in RS {
+            assert_eq!(decode_rs(insn, false), rv32, "rv32 {:#x}", insn);
+            assert_eq!(decode_rs(insn, true), rv64, "rv64 {:#x}", insn);
+        }
+    }
+}
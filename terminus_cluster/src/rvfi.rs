@@ -0,0 +1,105 @@
+use crate::retire::Retired;
+use std::collections::VecDeque;
+
+//rvfi-like retirement record, masks are relative to `mem_addr`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RvfiRecord {
+    pub order: u64,
+    pub insn: u32,
+    pub trap: bool,
+    pub mode: u8,
+    pub rs1_addr: u32,
+    pub rs2_addr: u32,
+    pub rs1_rdata: u64,
+    pub rs2_rdata: u64,
+    pub rd_addr: u32,
+    pub rd_wdata: u64,
+    pub pc_rdata: u64,
+    pub pc_wdata: u64,
+    pub mem_addr: u64,
+    pub mem_rmask: u32,
+    pub mem_wmask: u32,
+    pub mem_rdata: u64,
+    pub mem_wdata: u64,
+}
+
+impl RvfiRecord {
+    fn new(order: u64, r: &Retired) -> RvfiRecord {
+        let (rd_addr, rd_wdata) = r.rd.unwrap_or((0, 0));
+        let mut record = RvfiRecord {
+            order,
+            insn: r.insn,
+            trap: r.trap,
+            mode: r.privilege,
+            rs1_addr: r.rs1.0,
+            rs2_addr: r.rs2.0,
+            rs1_rdata: r.rs1.1,
+            rs2_rdata: r.rs2.1,
+            rd_addr,
+            rd_wdata,
+            pc_rdata: r.pc,
+            pc_wdata: r.next_pc,
+            ..Default::default()
+        };
+        if let Some(a) = r.mem.iter().find(|a| !a.write) {
+            record.mem_addr = a.addr;
+            record.mem_rmask = (1 << a.size) - 1;
+            record.mem_rdata = a.data;
+        }
+        if let Some(a) = r.mem.iter().find(|a| a.write) {
+            record.mem_addr = a.addr;
+            record.mem_wmask = (1 << a.size) - 1;
+            record.mem_wdata = a.data;
+        }
+        record
+    }
+}
+
+//the oldest records are dropped if sv does not drain in time
+pub struct RvfiRing {
+    records: VecDeque<RvfiRecord>,
+    depth: usize,
+    order: u64,
+    dropped: u64,
+    enabled: bool,
+}
+
+impl RvfiRing {
+    pub fn new(depth: usize) -> RvfiRing {
+        RvfiRing {
+            records: VecDeque::with_capacity(depth),
+            depth,
+            order: 0,
+            dropped: 0,
+            enabled: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn enable(&mut self, en: bool) {
+        self.enabled = en
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn push(&mut self, r: &Retired) {
+        if !self.enabled {
+            return;
+        }
+        if self.records.len() >= self.depth {
+            self.records.pop_front();
+            self.dropped += 1;
+        }
+        self.records.push_back(RvfiRecord::new(self.order, r));
+        self.order += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<RvfiRecord> {
+        self.records.pop_front()
+    }
+}
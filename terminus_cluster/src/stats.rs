@@ -0,0 +1,76 @@
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct PrivilegeCycles {
+    pub user: u64,
+    pub supervisor: u64,
+    pub machine: u64,
+}
+
+impl PrivilegeCycles {
+    pub fn get(&self, privilege: u8) -> Option<u64> {
+        match privilege {
+            0 => Some(self.user),
+            1 => Some(self.supervisor),
+            3 => Some(self.machine),
+            _ => None,
+        }
+    }
+
+    fn add(&mut self, privilege: u8) {
+        match privilege {
+            0 => self.user += 1,
+            1 => self.supervisor += 1,
+            _ => self.machine += 1,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct HartStats {
+    pub hartid: usize,
+    pub instret: u64,
+    //every step of a running hart counts one cycle, including steps skipped in wfi
+    pub cycles: u64,
+    pub wfi_cycles: u64,
+    //exceptions and interrupts taken
+    pub traps: u64,
+    pub privilege_cycles: PrivilegeCycles,
+}
+
+impl HartStats {
+    pub fn new(hartid: usize) -> HartStats {
+        HartStats {
+            hartid,
+            ..Default::default()
+        }
+    }
+
+    pub fn wfi(&mut self, privilege: u8) {
+        self.cycles += 1;
+        self.wfi_cycles += 1;
+        self.privilege_cycles.add(privilege);
+    }
+
+    pub fn step(&mut self, privilege: u8, retired: u64, trap: bool) {
+        self.cycles += 1;
+        self.instret += retired;
+        self.traps += trap as u64;
+        self.privilege_cycles.add(privilege);
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClusterStats<'a> {
+    //cluster_run_1step calls
+    pub steps: u64,
+    pub harts: &'a [HartStats],
+}
+
+impl ClusterStats<'_> {
+    pub fn dump(&self, path: &str) -> Result<(), String> {
+        let file =
+            std::fs::File::create(path).map_err(|e| format!("create {} fail: {}!", path, e))?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| format!("write {} fail: {}!", path, e))
+    }
+}
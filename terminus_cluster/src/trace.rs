@@ -0,0 +1,62 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+
+pub const TRACE_HEADER: &str = "hart,pc,route,region,addr,size,data,rw";
+
+//csv log of bus transactions of all harts in a cluster, shared by their CoreBus
+#[derive(Default)]
+pub struct BusTrace {
+    file: RefCell<Option<LineWriter<File>>>,
+}
+
+impl BusTrace {
+    pub fn new() -> BusTrace {
+        BusTrace::default()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.file.borrow().is_some()
+    }
+
+    //truncate `path` and trace from now on
+    pub fn start(&self, path: &str) -> Result<(), String> {
+        let mut file = LineWriter::new(
+            File::create(path).map_err(|e| format!("create bus trace {} fail: {}!", path, e))?,
+        );
+        writeln!(file, "{}", TRACE_HEADER)
+            .map_err(|e| format!("write bus trace {} fail: {}!", path, e))?;
+        *self.file.borrow_mut() = Some(file);
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.file.take();
+    }
+
+    //`route` is local or sys, `rw` is r, w or x(fetch)
+    #[allow(clippy::too_many_arguments)]
+    pub fn log(
+        &self,
+        hartid: usize,
+        pc: u64,
+        route: &str,
+        region: &str,
+        addr: u64,
+        size: usize,
+        data: u64,
+        rw: char,
+    ) {
+        let mut file = self.file.borrow_mut();
+        let file = match file.as_mut() {
+            Some(f) => f,
+            None => return,
+        };
+        writeln!(
+            file,
+            "{},{:#x},{},{},{:#x},{},{:#x},{}",
+            hartid, pc, route, region, addr, size, data, rw
+        )
+        .unwrap_or_else(|e| panic!("write bus trace fail: {}!", e))
+    }
+}
@@ -0,0 +1,125 @@
+use std::cell::RefCell;
+
+pub const WATCH_READ: u32 = 1;
+pub const WATCH_WRITE: u32 = 2;
+pub const WATCH_EXEC: u32 = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Watchpoint {
+    pub id: u32,
+    pub base: u64,
+    pub size: u64,
+    //WATCH_READ | WATCH_WRITE | WATCH_EXEC
+    pub kinds: u32,
+    //halt the hart after the hitting instruction
+    pub halt: bool,
+}
+
+impl Watchpoint {
+    fn hit(&self, addr: u64, size: u64, kind: u32) -> bool {
+        self.kinds & kind != 0 && addr < self.base + self.size && addr + size > self.base
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchHit {
+    pub id: u32,
+    pub pc: u64,
+    pub addr: u64,
+    //loaded or stored data, instruction for WATCH_EXEC
+    pub data: u64,
+    pub kind: u32,
+    pub halt: bool,
+}
+
+impl WatchHit {
+    pub fn kind_str(&self) -> &'static str {
+        match self.kind {
+            WATCH_READ => "read",
+            WATCH_WRITE => "write",
+            _ => "exec",
+        }
+    }
+}
+
+//watchpoints of one hart, data accesses are checked by its CoreBus
+#[derive(Default)]
+pub struct Watches {
+    points: RefCell<Vec<Watchpoint>>,
+    hits: RefCell<Vec<WatchHit>>,
+}
+
+impl Watches {
+    pub fn is_empty(&self) -> bool {
+        self.points.borrow().is_empty()
+    }
+
+    pub fn add(&self, w: Watchpoint) {
+        self.points.borrow_mut().push(w)
+    }
+
+    pub fn remove(&self, id: u32) {
+        self.points.borrow_mut().retain(|w| w.id != id)
+    }
+
+    //`pc` of the instruction accessing
+    pub fn check(&self, pc: u64, addr: u64, size: u64, data: u64, kind: u32) {
+        let mut hits = self.hits.borrow_mut();
+        for w in self
+            .points
+            .borrow()
+            .iter()
+            .filter(|w| w.hit(addr, size, kind))
+        {
+            hits.push(WatchHit {
+                id: w.id,
+                pc,
+                addr,
+                data,
+                kind,
+                halt: w.halt,
+            })
+        }
+    }
+
+    pub fn take_hits(&self) -> Vec<WatchHit> {
+        self.hits.take()
+    }
+}
+
+//"r", "w", "x" or any combination
+pub fn parse_kinds(s: &str) -> Result<u32, String> {
+    s.chars().try_fold(0, |kinds, c| match c {
+        'r' => Ok(kinds | WATCH_READ),
+        'w' => Ok(kinds | WATCH_WRITE),
+        'x' => Ok(kinds | WATCH_EXEC),
+        _ => Err(format!("invalid watch kinds {}, expect r, w or x!", s)),
+    })
+}
+
+//sv is called for every hit
+#[cfg(feature = "watch_callback")]
+pub fn watch_callback(handle: u32, hartid: usize, hit: &WatchHit) {
+    extern "C" {
+        fn cluster_watch_hit(
+            handle: u32,
+            id: u32,
+            hartid: u32,
+            pc: u64,
+            addr: u64,
+            data: u64,
+            kind: u32,
+        );
+    }
+    unsafe {
+        cluster_watch_hit(
+            handle,
+            hit.id,
+            hartid as u32,
+            hit.pc,
+            hit.addr,
+            hit.data,
+            hit.kind,
+        )
+    }
+}
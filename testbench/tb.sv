@@ -21,6 +21,11 @@
 `define ILM_ID 32'h0
 `define DLM_ID 32'h1
 
+//response of cluster_ext_* calls
+`define RESP_OKAY 32'd0
+`define RESP_SLVERR 32'd2
+`define RESP_DECERR 32'd3
+
 module TestModule(input bit clock);
     import "DPI-C" function void mb_get_space(string ch_name, output string space_name);
     import "DPI-C" function void mb_backdoor_write_u8(string space_name, longint unsigned addr, byte unsigned data);
@@ -33,25 +38,93 @@ module TestModule(input bit clock);
     import "DPI-C" function void mb_backdoor_read_u64(string space_name, longint unsigned addr, output longint unsigned data);
     import "DPI-C" function void mb_backdoor_write_string(string space_name, longint unsigned addr, string data);
     import "DPI-C" function void mb_backdoor_read_string(string space_name, longint unsigned addr, output string data);
-    import "DPI-C" function void cluster_init(int unsigned num_cores);
-    import "DPI-C" function void cluster_reset_core(int unsigned hartid, longint unsigned boot_addr);
+    import "DPI-C" function int unsigned cluster_create_default(int unsigned num_cores);
+    import "DPI-C" function int unsigned cluster_create(string path);
+    import "DPI-C" function int unsigned cluster_create_default_sparse(int unsigned num_cores);
+    import "DPI-C" function int unsigned cluster_add_ext_window(int unsigned handle, string name, int unsigned id, longint unsigned base, longint unsigned size);
+    import "DPI-C" function int unsigned cluster_local_region_id(int unsigned handle, int unsigned hartid, string name);
+    import "DPI-C" function int unsigned cluster_set_irq_line(int unsigned handle, int unsigned src, int unsigned level);
+    import "DPI-C" function void cluster_reset_core(int unsigned handle, int unsigned hartid, longint unsigned boot_addr);
+    import "DPI-C" function void cluster_halt_core(int unsigned handle, int unsigned hartid);
+    import "DPI-C" function void cluster_resume_core(int unsigned handle, int unsigned hartid);
+    import "DPI-C" function int unsigned cluster_core_state(int unsigned handle, int unsigned hartid);
+    import "DPI-C" function longint unsigned cluster_read_gpr(int unsigned handle, int unsigned hartid, int unsigned idx);
+    import "DPI-C" function void cluster_write_gpr(int unsigned handle, int unsigned hartid, int unsigned idx, longint unsigned value);
+    import "DPI-C" function longint unsigned cluster_read_pc(int unsigned handle, int unsigned hartid);
+    import "DPI-C" function void cluster_write_pc(int unsigned handle, int unsigned hartid, longint unsigned pc);
+    import "DPI-C" function int unsigned cluster_read_csr(int unsigned handle, int unsigned hartid, int unsigned csr, output longint unsigned value);
+    import "DPI-C" function int unsigned cluster_write_csr(int unsigned handle, int unsigned hartid, int unsigned csr, longint unsigned value);
+    import "DPI-C" function void cluster_commit_log(int unsigned handle, int unsigned hartid, int unsigned enable);
+    import "DPI-C" function void cluster_rvfi_enable(int unsigned handle, int unsigned hartid, int unsigned enable);
+    import "DPI-C" function int unsigned cluster_rvfi_pop(int unsigned handle, int unsigned hartid,
+                                                          output longint unsigned order, output int unsigned insn,
+                                                          output int unsigned trap, output int unsigned mode,
+                                                          output int unsigned rs1_addr, output int unsigned rs2_addr,
+                                                          output longint unsigned rs1_rdata, output longint unsigned rs2_rdata,
+                                                          output int unsigned rd_addr, output longint unsigned rd_wdata,
+                                                          output longint unsigned pc_rdata, output longint unsigned pc_wdata,
+                                                          output longint unsigned mem_addr,
+                                                          output int unsigned mem_rmask, output int unsigned mem_wmask,
+                                                          output longint unsigned mem_rdata, output longint unsigned mem_wdata);
+    import "DPI-C" function longint unsigned cluster_load_elf(int unsigned handle, string path, longint unsigned hartmask);
+    import "DPI-C" function int unsigned cluster_symbol_addr(int unsigned handle, string name, output longint unsigned addr);
+    import "DPI-C" function int unsigned cluster_watch_add(int unsigned handle, longint unsigned hartmask, longint unsigned base, longint unsigned size, int unsigned kinds, int unsigned halt);
+    import "DPI-C" function int unsigned cluster_watch_add_symbol(int unsigned handle, longint unsigned hartmask, string name, longint unsigned size, int unsigned kinds, int unsigned halt);
+    import "DPI-C" function void cluster_watch_remove(int unsigned handle, int unsigned id);
+    import "DPI-C" function int unsigned cluster_bus_trace_start(int unsigned handle, string path);
+    import "DPI-C" function void cluster_bus_trace_stop(int unsigned handle);
+    import "DPI-C" function int unsigned cluster_save_checkpoint(int unsigned handle, string path);
+    import "DPI-C" function int unsigned cluster_restore_checkpoint(int unsigned handle, string path);
+    import "DPI-C" function longint unsigned cluster_stat_instret(int unsigned handle, int unsigned hartid);
+    import "DPI-C" function longint unsigned cluster_stat_cycles(int unsigned handle, int unsigned hartid);
+    import "DPI-C" function longint unsigned cluster_stat_wfi_cycles(int unsigned handle, int unsigned hartid);
+    import "DPI-C" function longint unsigned cluster_stat_traps(int unsigned handle, int unsigned hartid);
+    import "DPI-C" function longint unsigned cluster_stat_privilege_cycles(int unsigned handle, int unsigned hartid, int unsigned privilege);
+    import "DPI-C" function int unsigned cluster_stats_dump(int unsigned handle, string path);
+    import "DPI-C" function int unsigned cluster_gdb_listen(int unsigned handle, int unsigned port, int unsigned wait);
+    import "DPI-C" function void cluster_lockstep_enable(int unsigned handle, int unsigned hartid, int unsigned depth);
+    import "DPI-C" function void cluster_lockstep_disable(int unsigned handle, int unsigned hartid);
+    import "DPI-C" function int unsigned cluster_lockstep_check(int unsigned handle, int unsigned hartid,
+                                                                longint unsigned pc, int unsigned insn,
+                                                                int unsigned rd_addr, longint unsigned rd_wdata,
+                                                                longint unsigned mem_addr, longint unsigned mem_wdata,
+                                                                int unsigned mem_wmask);
     import "DPI-C" context task mb_server_run_async();
-    import "DPI-C" task cluster_run_1step();
+    import "DPI-C" task cluster_run_1step(int unsigned handle);
+    import "DPI-C" function int unsigned cluster_step_core(int unsigned handle, int unsigned hartid, longint unsigned n);
+    import "DPI-C" function longint unsigned cluster_step_cores(int unsigned handle, longint unsigned n);
+    import "DPI-C" function int unsigned cluster_tick_mtime(int unsigned handle, longint unsigned n);
+    import "DPI-C" function void cluster_mem_write_bd(int unsigned handle, int unsigned id, longint unsigned addr, byte unsigned data);
+    import "DPI-C" function void cluster_mem_read_bd(int unsigned handle, int unsigned id, longint unsigned addr, output byte unsigned data);
     export "DPI-C" function mem_write_bd;
     export "DPI-C" function mem_read_bd;
     export "DPI-C" function mb_exit;
-    export "DPI-C" task cluster_ext_write_u8;
-    export "DPI-C" task cluster_ext_read_u8;
-    export "DPI-C" task cluster_ext_write_u16;
-    export "DPI-C" task cluster_ext_read_u16;
-    export "DPI-C" task cluster_ext_write_u32;
-    export "DPI-C" task cluster_ext_read_u32;
-    export "DPI-C" task cluster_ext_write_u64;
-    export "DPI-C" task cluster_ext_read_u64;
+    export "DPI-C" function cluster_ext_write_u8;
+    export "DPI-C" function cluster_ext_read_u8;
+    export "DPI-C" function cluster_ext_write_u16;
+    export "DPI-C" function cluster_ext_read_u16;
+    export "DPI-C" function cluster_ext_write_u32;
+    export "DPI-C" function cluster_ext_read_u32;
+    export "DPI-C" function cluster_ext_write_u64;
+    export "DPI-C" function cluster_ext_read_u64;
+`ifdef EXT_BURST
+    import "DPI-C" function void cluster_burst_get(chandle buf, int unsigned len, output byte unsigned data[]);
+    import "DPI-C" function void cluster_burst_put(chandle buf, int unsigned len, input byte unsigned data[]);
+    export "DPI-C" function cluster_ext_write_bytes;
+    export "DPI-C" function cluster_ext_read_bytes;
+`endif
+`ifdef SV_CHECKPOINT
+    export "DPI-C" function cluster_sv_checkpoint;
+`endif
+`ifdef WATCH_CALLBACK
+    export "DPI-C" function cluster_watch_hit;
+`endif
     export "DPI-C" function tb_sv_call;    
     export "DPI-C" function poll_event;
 
+`ifndef GLOBAL_SPARSE
     bit [7:0] global[`GLOBAL_SIZE];
+`endif
     bit [7:0] core0_ilm[`ILM_SIZE];
     bit [7:0] core0_dlm[`DLM_SIZE];
     bit [7:0] core1_ilm[`ILM_SIZE];
@@ -60,6 +133,8 @@ module TestModule(input bit clock);
     bit [7:0] core2_dlm[`DLM_SIZE];
 
     bit tb_clock;
+
+    int unsigned cluster;
    
     bit reset_n;
 
@@ -79,15 +154,25 @@ module TestModule(input bit clock);
     end
 
     initial begin
-        cluster_init(3);
-        cluster_reset_core(0, 64'h80100000);
-        cluster_reset_core(1, 64'h80100000);
-        cluster_reset_core(2, 64'h80100000);
+        string elf_file;
+        longint unsigned entry = 64'h80100000;
+`ifdef GLOBAL_SPARSE
+        cluster = cluster_create_default_sparse(3);
+`else
+        cluster = cluster_create_default(3);
+`endif
+        //+elf=<path> loads the elf directly and boots from its entry point
+        if ($value$plusargs("elf=%s", elf_file)) begin
+            entry = cluster_load_elf(cluster, elf_file, 64'h7);
+        end
+        cluster_reset_core(cluster, 0, entry);
+        cluster_reset_core(cluster, 1, entry);
+        cluster_reset_core(cluster, 2, entry);
     end
 
     always @(posedge clock) begin
         if (timeout_cnt > 1000) begin
-            cluster_run_1step();
+            cluster_run_1step(cluster);
         end
     end
 
@@ -180,7 +265,11 @@ module TestModule(input bit clock);
 
     function automatic void mem_write_bd(int unsigned id, longint unsigned addr, byte unsigned data);
         case(id)
+`ifdef GLOBAL_SPARSE
+            `GLOBAL_ID: cluster_mem_write_bd(cluster, id, addr, data);
+`else
             `GLOBAL_ID: global[addr[31:0]-`GLOBAL_BASE] = data;
+`endif
             `CORE0_ID | `ILM_ID: core0_ilm[addr[31:0]] = data;
             `CORE0_ID | `DLM_ID: core0_dlm[addr[31:0]-`ILM_SIZE] = data;
             `CORE1_ID | `ILM_ID: core1_ilm[addr[31:0]] = data;
@@ -193,7 +282,11 @@ module TestModule(input bit clock);
 
     function automatic void  mem_read_bd(int unsigned id, longint unsigned addr, output byte unsigned data);
         case(id)
+`ifdef GLOBAL_SPARSE
+            `GLOBAL_ID: cluster_mem_read_bd(cluster, id, addr, data);
+`else
             `GLOBAL_ID: data = global[addr[31:0]-`GLOBAL_BASE];
+`endif
             `CORE0_ID | `ILM_ID: data = core0_ilm[addr[31:0]];
             `CORE0_ID | `DLM_ID: data = core0_dlm[addr[31:0]-`ILM_SIZE];
             `CORE1_ID | `ILM_ID: data = core1_ilm[addr[31:0]];
@@ -204,50 +297,153 @@ module TestModule(input bit clock);
         endcase
     endfunction
 
-    function automatic void cluster_ext_write_u8(int unsigned id, longint unsigned addr, byte unsigned data);
-        mem_write_bd(id, addr, data);
+    //global window is wider than the memory backing it, the rest decodes to nothing
+    function automatic int unsigned ext_resp(int unsigned id, longint unsigned addr, int unsigned len);
+        if (id == `GLOBAL_ID && (addr < `GLOBAL_BASE || addr + len > `GLOBAL_BASE + `GLOBAL_SIZE)) begin
+            return `RESP_DECERR;
+        end
+        return `RESP_OKAY;
+    endfunction
+
+    function automatic int unsigned cluster_ext_write_u8(int unsigned id, longint unsigned addr, byte unsigned data);
+        int unsigned resp = ext_resp(id, addr, 1);
+        if (resp == `RESP_OKAY) begin
+            mem_write_bd(id, addr, data);
+        end
+        return resp;
+    endfunction
+
+    function automatic int unsigned cluster_ext_read_u8(int unsigned id, longint unsigned addr, output byte unsigned data);
+        int unsigned resp = ext_resp(id, addr, 1);
+        data = 0;
+        if (resp == `RESP_OKAY) begin
+            mem_read_bd(id, addr, data);
+        end
+        return resp;
+    endfunction
+
+    function automatic int unsigned cluster_ext_write_u16(int unsigned id, longint unsigned addr, shortint unsigned data);
+        int unsigned resp = ext_resp(id, addr, 2);
+        if (resp == `RESP_OKAY) begin
+            for (longint i = 0; i <2; i++) begin
+                mem_write_bd(id, addr+i, data[i[31:0]*8+:8]);
+            end
+        end
+        return resp;
     endfunction
 
-    function automatic void cluster_ext_read_u8(int unsigned id, longint unsigned addr, output byte unsigned data);
-        mem_read_bd(id, addr, data);
+    function automatic int unsigned cluster_ext_read_u16(int unsigned id, longint unsigned addr, output shortint unsigned data);
+        int unsigned resp = ext_resp(id, addr, 2);
+        data = 0;
+        if (resp == `RESP_OKAY) begin
+            for (longint i = 0; i <2; i++) begin
+                mem_read_bd(id, addr+i, data[i[31:0]*8+:8]);
+            end
+        end
+        return resp;
     endfunction
 
-    function automatic void cluster_ext_write_u16(int unsigned id, longint unsigned addr, shortint unsigned data);
-        for (longint i = 0; i <2; i++) begin
-            mem_write_bd(id, addr+i, data[i[31:0]*8+:8]);
+    function automatic int unsigned cluster_ext_write_u32(int unsigned id, longint unsigned addr, int unsigned data);
+        int unsigned resp = ext_resp(id, addr, 4);
+        if (resp == `RESP_OKAY) begin
+            for (longint i = 0; i <4; i++) begin
+                mem_write_bd(id, addr+i, data[i[31:0]*8+:8]);
+            end
         end
+        return resp;
     endfunction
 
-    function automatic void  cluster_ext_read_u16(int unsigned id, longint unsigned addr, output shortint unsigned data);
-        for (longint i = 0; i <2; i++) begin
-            mem_read_bd(id, addr+i, data[i[31:0]*8+:8]);
+    function automatic int unsigned cluster_ext_read_u32(int unsigned id, longint unsigned addr, output int unsigned data);
+        int unsigned resp = ext_resp(id, addr, 4);
+        data = 0;
+        if (resp == `RESP_OKAY) begin
+            for (longint i = 0; i <4; i++) begin
+                mem_read_bd(id, addr+i, data[i[31:0]*8+:8]);
+            end
         end
+        return resp;
     endfunction
 
-    function automatic void cluster_ext_write_u32(int unsigned id, longint unsigned addr, int unsigned data);
-        for (longint i = 0; i <4; i++) begin
-            mem_write_bd(id, addr+i, data[i[31:0]*8+:8]);
+    function automatic int unsigned cluster_ext_write_u64(int unsigned id, longint unsigned addr, longint unsigned data);
+        int unsigned resp = ext_resp(id, addr, 8);
+        if (resp == `RESP_OKAY) begin
+            for (longint i = 0; i <8; i++) begin
+                mem_write_bd(id, addr+i, data[i[31:0]*8+:8]);
+            end
         end
+        return resp;
     endfunction
 
-    function automatic void cluster_ext_read_u32(int unsigned id, longint unsigned addr, output int unsigned data);
-        for (longint i = 0; i <4; i++) begin
-            mem_read_bd(id, addr+i, data[i[31:0]*8+:8]);
+    function automatic int unsigned cluster_ext_read_u64(int unsigned id, longint unsigned addr, output longint unsigned data);
+        int unsigned resp = ext_resp(id, addr, 8);
+        data = 0;
+        if (resp == `RESP_OKAY) begin
+            for (longint i = 0; i <8; i++) begin
+                mem_read_bd(id, addr+i, data[i[31:0]*8+:8]);
+            end
         end
+        return resp;
     endfunction
 
-    function automatic void cluster_ext_write_u64(int unsigned id, longint unsigned addr, longint unsigned data);
-        for (longint i = 0; i <8; i++) begin
-            mem_write_bd(id, addr+i, data[i[31:0]*8+:8]);
+`ifdef EXT_BURST
+    function automatic int unsigned cluster_ext_write_bytes(int unsigned id, longint unsigned addr, chandle buf, int unsigned len);
+        byte unsigned data[] = new[len];
+        int unsigned resp = ext_resp(id, addr, len);
+        cluster_burst_get(buf, len, data);
+        if (resp == `RESP_OKAY) begin
+            foreach (data[i]) begin
+                mem_write_bd(id, addr+i, data[i]);
+            end
         end
+        return resp;
     endfunction
 
-    function automatic void cluster_ext_read_u64(int unsigned id, longint unsigned addr, output longint unsigned data);
-        for (longint i = 0; i <8; i++) begin
-            mem_read_bd(id, addr+i, data[i[31:0]*8+:8]);
+    function automatic int unsigned cluster_ext_read_bytes(int unsigned id, longint unsigned addr, chandle buf, int unsigned len);
+        byte unsigned data[] = new[len];
+        int unsigned resp = ext_resp(id, addr, len);
+        if (resp == `RESP_OKAY) begin
+            foreach (data[i]) begin
+                mem_read_bd(id, addr+i, data[i]);
+            end
         end
+        cluster_burst_put(buf, len, data);
+        return resp;
     endfunction
+`endif
 
+`ifdef WATCH_CALLBACK
+    //kind 1: read, 2: write, 4: exec
+    function automatic void cluster_watch_hit(int unsigned handle, int unsigned id, int unsigned hartid, longint unsigned pc, longint unsigned addr, longint unsigned data, int unsigned kind);
+        $display("[%0t] watch%0d: core%0d pc 0x%0x kind %0d 0x%0x = 0x%0x", $time, id, hartid, pc, kind, addr, data);
+    endfunction
+`endif
+
+`ifdef SV_CHECKPOINT
+    //dpi memories are saved next to the rust checkpoint at `path`
+    function automatic void cluster_sv_checkpoint(int unsigned handle, string path, int unsigned save);
+        if (save) begin
+`ifndef GLOBAL_SPARSE
+            $writememh({path, ".global.hex"}, global);
+`endif
+            $writememh({path, ".core0_ilm.hex"}, core0_ilm);
+            $writememh({path, ".core0_dlm.hex"}, core0_dlm);
+            $writememh({path, ".core1_ilm.hex"}, core1_ilm);
+            $writememh({path, ".core1_dlm.hex"}, core1_dlm);
+            $writememh({path, ".core2_ilm.hex"}, core2_ilm);
+            $writememh({path, ".core2_dlm.hex"}, core2_dlm);
+        end else begin
+`ifndef GLOBAL_SPARSE
+            $readmemh({path, ".global.hex"}, global);
+`endif
+            $readmemh({path, ".core0_ilm.hex"}, core0_ilm);
+            $readmemh({path, ".core0_dlm.hex"}, core0_dlm);
+            $readmemh({path, ".core1_ilm.hex"}, core1_ilm);
+            $readmemh({path, ".core1_dlm.hex"}, core1_dlm);
+            $readmemh({path, ".core2_ilm.hex"}, core2_ilm);
+            $readmemh({path, ".core2_dlm.hex"}, core2_dlm);
+        end
+    endfunction
+`endif
 
     bit event_table[10];
 
@@ -11,7 +11,8 @@
 static uint64_t trace_count = 0;
 
 extern "C" {
-    void cluster_statics();
+    unsigned int cluster_count();
+    void cluster_statics(unsigned int handle);
     void cluster_step() {
         while(trace_count%2==1){}
     }
@@ -51,7 +52,9 @@ int main(int argc, char** argv)
   std::cout << "Done!" << std::endl;
   std::cout << "CPUs statics when finish:" << std::endl;
   std::cout << "--------------------------" << std::endl;
-  cluster_statics();
+  for (unsigned int i = 0; i < cluster_count(); i++) {
+    cluster_statics(i);
+  }
   std::cout << "--------------------------" << std::endl;
   return ret;
 }
\ No newline at end of file